@@ -0,0 +1,59 @@
+//! Lookups of absent keys at the bottom of a 100-tier chain, with and
+//! without a bloom filter, for a growing number of keys in the chain.
+//!
+//! Run with `cargo bench --bench deep_miss`.
+
+extern crate tiered_map;
+
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use tiered_map::TieredMap;
+
+const DEPTH: usize = 100;
+const BATCHES: u32 = 20;
+const MISSES: usize = 1_000;
+
+// spreads `keys` evenly over the chain, leaking the tiers so the chain can
+// be built in a loop
+fn chain(mut root: TieredMap<'static, usize, usize>,
+         keys: usize)
+         -> &'static TieredMap<'static, usize, usize> {
+    let per_tier = keys / DEPTH;
+    root.extend((0..per_tier).map(|k| (k, k)));
+    let mut tier: &'static TieredMap<_, _> = Box::leak(Box::new(root));
+    for i in 1..DEPTH {
+        let mut child = tier.new_scope();
+        child.extend((i * per_tier..(i + 1) * per_tier).map(|k| (k, k)));
+        tier = Box::leak(Box::new(child));
+    }
+    tier
+}
+
+// the fastest batch is the one least disturbed by the rest of the machine
+fn time_misses(tier: &TieredMap<usize, usize>, keys: usize) -> Duration {
+    let mut best = Duration::MAX;
+    for _ in 0..BATCHES {
+        let start = Instant::now();
+        for k in keys..keys + MISSES {
+            black_box(tier.get(black_box(&k)));
+        }
+        best = best.min(start.elapsed() / MISSES as u32);
+    }
+    best
+}
+
+fn main() {
+    println!("{:>6} {:>12} {:>12}", "keys", "plain", "bloom");
+    for &keys in &[100, 300, 500, 1_000, 3_000] {
+        let plain = chain(TieredMap::new(), keys);
+        let bloom = chain(TieredMap::with_bloom(), keys);
+        assert_eq!(plain.len(), keys);
+        assert_eq!(bloom.len(), keys);
+
+        println!("{:>6} {:>12.1?} {:>12.1?}",
+                 keys,
+                 time_misses(plain, keys),
+                 time_misses(bloom, keys));
+    }
+}
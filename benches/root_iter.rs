@@ -0,0 +1,57 @@
+//! Iteration over a root map through `iter()`, against the plain
+//! `hash_map::Iter` of the map underneath.
+//!
+//! Run with `cargo bench --bench root_iter`.
+
+extern crate tiered_map;
+
+use std::collections::HashMap;
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use tiered_map::TieredMap;
+
+const BATCHES: u32 = 20;
+
+// the fastest batch is the one least disturbed by the rest of the machine
+fn time<F>(passes: u32, mut f: F) -> Duration
+    where F: FnMut() -> u64
+{
+    let mut best = Duration::MAX;
+    for _ in 0..BATCHES {
+        let start = Instant::now();
+        for _ in 0..passes {
+            black_box(f());
+        }
+        best = best.min(start.elapsed() / passes);
+    }
+    best
+}
+
+// drives the iterator through `next`, as a `for` loop with an early exit would
+fn sum_by_next<'a, I>(iter: I) -> u64
+    where I: Iterator<Item = (&'a u64, &'a u64)>
+{
+    let mut sum = 0;
+    for (_, v) in iter {
+        sum += *v;
+    }
+    sum
+}
+
+fn main() {
+    println!("{:>7} {:>12} {:>12} {:>12} {:>12}",
+             "entries", "iter fold", "map fold", "iter next", "map next");
+    for &entries in &[16, 1_000, 100_000] {
+        let tm = TieredMap::from_pairs((0..entries).map(|k| (k, k)));
+        let map: &HashMap<_, _> = tm.as_hashmap().unwrap();
+        let passes = (1_000_000 / entries) as u32;
+
+        println!("{:>7} {:>12.1?} {:>12.1?} {:>12.1?} {:>12.1?}",
+                 entries,
+                 time(passes, || black_box(&tm).iter().fold(0, |sum, (_, v)| sum + v)),
+                 time(passes, || black_box(map).iter().fold(0, |sum, (_, v)| sum + v)),
+                 time(passes, || sum_by_next(black_box(&tm).iter())),
+                 time(passes, || sum_by_next(black_box(map).iter())));
+    }
+}
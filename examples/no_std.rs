@@ -0,0 +1,22 @@
+//! Uses the crate from a `#![no_std]` library, to check that the `no_std`
+//! feature leaves no dependency on `std`.
+//!
+//! Build with `cargo build --example no_std --features no_std`, adding
+//! `--target` for a target without `std`.
+
+#![no_std]
+
+extern crate tiered_map;
+
+use tiered_map::TieredMap;
+
+/// Looks up a shadowed and an inherited key through a child scope.
+pub fn lookup() -> (Option<u32>, Option<u32>) {
+    let mut root = TieredMap::new();
+    root.insert(1, 10);
+    root.insert(2, 20);
+
+    let mut scope = root.new_scope();
+    scope.insert(1, 11);
+    (scope.get(&1).copied(), scope.get(&2).copied())
+}
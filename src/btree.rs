@@ -0,0 +1,274 @@
+
+use core::borrow::Borrow;
+use core::iter::Peekable;
+use alloc::collections::BTreeMap;
+use alloc::collections::btree_map;
+use alloc::vec::Vec;
+
+/// A tiered map backed by `BTreeMap`, iterating in key order.
+pub struct TieredBTreeMap<'a, K: 'a, V: 'a> {
+    parent: Option<&'a TieredBTreeMap<'a, K, V>>,
+    map: BTreeMap<K, V>,
+    parent_size: usize,
+    // number of inherited keys hidden by this tier
+    shadowed: usize,
+}
+
+impl<'a, K, V> TieredBTreeMap<'a, K, V>
+    where K: Ord
+{
+    pub fn new() -> Self {
+        TieredBTreeMap {
+            parent: None,
+            map: BTreeMap::new(),
+            parent_size: 0,
+            shadowed: 0,
+        }
+    }
+
+    pub fn new_scope(&self) -> TieredBTreeMap<'_, K, V> {
+        // skip empty tiers
+        if let Some(p) = self.parent {
+            if self.map.is_empty() {
+                return p.new_scope();
+            }
+        }
+
+        TieredBTreeMap {
+            parent: Some(self),
+            map: BTreeMap::new(),
+            parent_size: self.len(),
+            shadowed: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.parent_size - self.shadowed + self.map.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn get<Q>(&self, k: &Q) -> Option<&V>
+        where K: Borrow<Q>,
+              Q: ?Sized + Ord
+    {
+        let mut tier = self;
+        loop {
+            if let Some(v) = tier.map.get(k) {
+                return Some(v);
+            }
+            match tier.parent {
+                Some(p) => tier = p,
+                None => return None,
+            }
+        }
+    }
+
+    pub fn contains_key<Q>(&self, k: &Q) -> bool
+        where K: Borrow<Q>,
+              Q: ?Sized + Ord
+    {
+        self.get(k).is_some()
+    }
+
+    pub fn insert(&mut self, k: K, v: V) -> Option<V> {
+        match self.map.entry(k) {
+            btree_map::Entry::Occupied(mut e) => Some(e.insert(v)),
+            btree_map::Entry::Vacant(e) => {
+                if self.parent.is_some_and(|p| p.contains_key(e.key())) {
+                    self.shadowed += 1;
+                }
+                e.insert(v);
+                None
+            }
+        }
+    }
+
+    /// Iterates over the visible entries in ascending key order.
+    pub fn iter(&self) -> Iter<'_, K, V> {
+        let mut tiers = Vec::new();
+        let mut tier = self;
+        loop {
+            tiers.push(tier.map.iter().peekable());
+            match tier.parent {
+                Some(p) => tier = p,
+                None => break,
+            }
+        }
+
+        Iter {
+            tiers,
+            remaining: self.len(),
+        }
+    }
+
+    pub fn keys(&self) -> Keys<'_, K, V> {
+        Keys { inner: self.iter() }
+    }
+
+    pub fn values(&self) -> Values<'_, K, V> {
+        Values { inner: self.iter() }
+    }
+}
+
+impl<'a, K, V> Default for TieredBTreeMap<'a, K, V>
+    where K: Ord
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Merges the sorted tiers, letting the nearest tier win on equal keys.
+pub struct Iter<'a, K: 'a, V: 'a> {
+    // nearest tier first
+    tiers: Vec<Peekable<btree_map::Iter<'a, K, V>>>,
+    remaining: usize,
+}
+
+impl<'a, K, V> Iterator for Iter<'a, K, V>
+    where K: Ord
+{
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut min: Option<(usize, &'a K)> = None;
+        for (i, tier) in self.tiers.iter_mut().enumerate() {
+            if let Some(&(k, _)) = tier.peek() {
+                if min.is_none_or(|(_, m)| k < m) {
+                    min = Some((i, k));
+                }
+            }
+        }
+
+        let (i, k) = min?;
+
+        // drop the entries shadowed by the one being yielded
+        for tier in &mut self.tiers[i + 1..] {
+            if tier.peek().is_some_and(|&(pk, _)| pk == k) {
+                tier.next();
+            }
+        }
+
+        self.remaining -= 1;
+        self.tiers[i].next()
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl<'a, K, V> ExactSizeIterator for Iter<'a, K, V>
+    where K: Ord
+{
+    #[inline]
+    fn len(&self) -> usize {
+        self.remaining
+    }
+}
+
+pub struct Keys<'a, K: 'a, V: 'a> {
+    inner: Iter<'a, K, V>,
+}
+
+impl<'a, K, V> Iterator for Keys<'a, K, V>
+    where K: Ord
+{
+    type Item = &'a K;
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next().map(|(k, _)| k)
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+impl<'a, K, V> ExactSizeIterator for Keys<'a, K, V>
+    where K: Ord
+{
+    #[inline]
+    fn len(&self) -> usize {
+        self.inner.len()
+    }
+}
+
+pub struct Values<'a, K: 'a, V: 'a> {
+    inner: Iter<'a, K, V>,
+}
+
+impl<'a, K, V> Iterator for Values<'a, K, V>
+    where K: Ord
+{
+    type Item = &'a V;
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next().map(|(_, v)| v)
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+impl<'a, K, V> ExactSizeIterator for Values<'a, K, V>
+    where K: Ord
+{
+    #[inline]
+    fn len(&self) -> usize {
+        self.inner.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TieredBTreeMap;
+
+    #[test]
+    fn sorted_iter() {
+        let mut tm1 = TieredBTreeMap::new();
+        for &(k, v) in &[("a", 1), ("c", 1), ("e", 1), ("g", 1)] {
+            tm1.insert(k, v);
+        }
+
+        let mut tm2 = tm1.new_scope();
+        for &(k, v) in &[("b", 2), ("c", 2), ("f", 2), ("h", 2)] {
+            tm2.insert(k, v);
+        }
+
+        assert_eq!(tm2.len(), 7);
+        assert_eq!(tm2.iter().len(), 7);
+        assert_eq!(tm2.iter().collect::<Vec<_>>(),
+                   vec![(&"a", &1), (&"b", &2), (&"c", &2), (&"e", &1), (&"f", &2),
+                        (&"g", &1), (&"h", &2)]);
+        assert_eq!(tm2.keys().cloned().collect::<Vec<_>>(),
+                   vec!["a", "b", "c", "e", "f", "g", "h"]);
+        assert_eq!(tm2.values().cloned().collect::<Vec<_>>(), vec![1, 2, 2, 1, 2, 1, 2]);
+    }
+
+    #[test]
+    fn scopes() {
+        let mut tm1 = TieredBTreeMap::new();
+        tm1.insert(1, "a");
+
+        let tm2 = tm1.new_scope();
+        let mut tm3 = tm2.new_scope();
+        tm3.insert(1, "b");
+        tm3.insert(2, "b");
+
+        assert_eq!(tm3.get(&1), Some(&"b"));
+        assert_eq!(tm1.get(&1), Some(&"a"));
+        assert!(tm3.contains_key(&2));
+        assert!(!tm2.contains_key(&2));
+        assert_eq!(tm3.len(), 2);
+    }
+}
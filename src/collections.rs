@@ -0,0 +1,30 @@
+//! The hash collections the maps are built on: `std`'s by default, or
+//! `hashbrown`'s with the `no_std` feature.
+
+use core::hash::Hasher;
+
+#[cfg(not(feature = "no_std"))]
+pub use std::collections::{HashMap, HashSet, TryReserveError};
+#[cfg(not(feature = "no_std"))]
+pub use std::collections::hash_map::{self, RandomState};
+
+#[cfg(feature = "no_std")]
+pub use hashbrown::{HashMap, HashSet, TryReserveError};
+#[cfg(feature = "no_std")]
+pub use hashbrown::hash_map;
+#[cfg(feature = "no_std")]
+pub use hashbrown::DefaultHashBuilder as RandomState;
+
+/// Returns a hasher seeded the same way every time, unlike the ones built
+/// by `RandomState`.
+#[cfg(not(feature = "no_std"))]
+pub fn fixed_hasher() -> impl Hasher {
+    hash_map::DefaultHasher::new()
+}
+
+#[cfg(feature = "no_std")]
+pub fn fixed_hasher() -> impl Hasher {
+    use core::hash::BuildHasher;
+
+    foldhash::fast::FixedState::default().build_hasher()
+}
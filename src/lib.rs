@@ -1,18 +1,60 @@
+#![cfg_attr(feature = "no_std", no_std)]
 
-use std::ops::Index;
-use std::cmp::{PartialEq, Eq};
-use std::hash::{Hash, BuildHasher};
-use std::borrow::Borrow;
-use std::collections::HashMap;
-use std::collections::hash_map::{self, RandomState};
-use std::fmt::{self, Debug, Formatter};
-use std::iter::FromIterator;
+#[cfg(not(feature = "no_std"))]
+extern crate core;
+extern crate alloc;
+#[cfg(feature = "no_std")]
+extern crate hashbrown;
+#[cfg(feature = "no_std")]
+extern crate foldhash;
+#[cfg(feature = "serde")]
+extern crate serde;
+#[cfg(feature = "rayon")]
+extern crate rayon;
+
+use core::ops::Index;
+use core::cmp::{PartialEq, Eq};
+use core::hash::{Hash, Hasher, BuildHasher};
+use core::error::Error;
+use core::mem;
+use core::sync::atomic::{AtomicU64, Ordering};
+use core::fmt::{self, Debug, Display, Formatter};
+use core::iter::{self, FromIterator};
+#[cfg(feature = "serde")]
+use core::marker::PhantomData;
+use alloc::borrow::{Borrow, Cow, ToOwned};
+use alloc::boxed::Box;
+use alloc::rc::Rc;
+use alloc::vec;
+use alloc::vec::Vec;
+
+use collections::{HashMap, HashSet, TryReserveError, RandomState};
+use collections::hash_map;
+
+pub mod btree;
+pub mod shared;
+mod collections;
+
+pub use btree::TieredBTreeMap;
+pub use shared::SharedTieredMap;
 
 pub struct TieredMap<'a, K: 'a, V: 'a, H: 'a = RandomState> {
     parent: Option<&'a TieredMap<'a, K, V, H>>,
     map: HashMap<K, V, H>,
+    // total capacity of the linked parent tiers when this scope was created
     parent_cap: usize,
+    // visible entries in the parent when this scope was created
     parent_size: usize,
+    // number of inherited keys hidden by this tier
+    shadowed: usize,
+    // inherited keys removed from this tier
+    removed: HashSet<K>,
+    // summary of every key reachable from this tier, boxed so that tiers
+    // without one stay small
+    bloom: Option<Box<Bloom>>,
+    id: ScopeId,
+    // how many more tiers may be linked below this one, if capped
+    scope_limit: Option<usize>,
 }
 
 macro_rules! tm {
@@ -22,8 +64,52 @@ macro_rules! tm {
             map: $map,
             parent_cap: $parent_cap,
             parent_size: $parent_size,
+            shadowed: 0,
+            removed: HashSet::new(),
+            bloom: None,
+            id: ScopeId::next(),
+            scope_limit: None,
+        }
+    }
+}
+
+const BLOOM_BITS: usize = 1024;
+
+#[derive(Clone)]
+struct Bloom {
+    bits: [u64; BLOOM_BITS / 64],
+}
+
+impl Bloom {
+    fn new() -> Self {
+        Bloom { bits: [0; BLOOM_BITS / 64] }
+    }
+
+    // two probes, taken from the low and high halves of the hash
+    fn probes(hash: u64) -> [usize; 2] {
+        [hash as usize % BLOOM_BITS, (hash >> 32) as usize % BLOOM_BITS]
+    }
+
+    fn insert(&mut self, hash: u64) {
+        for &bit in &Bloom::probes(hash) {
+            self.bits[bit / 64] |= 1 << (bit % 64);
         }
     }
+
+    fn may_contain(&self, hash: u64) -> bool {
+        Bloom::probes(hash).iter().all(|&bit| self.bits[bit / 64] & (1 << (bit % 64)) != 0)
+    }
+}
+
+/// Identifies a single tier, see `TieredMap::id`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ScopeId(u64);
+
+impl ScopeId {
+    fn next() -> Self {
+        static NEXT: AtomicU64 = AtomicU64::new(0);
+        ScopeId(NEXT.fetch_add(1, Ordering::Relaxed))
+    }
 }
 
 impl<'a, K, V> TieredMap<'a, K, V, RandomState>
@@ -36,6 +122,49 @@ impl<'a, K, V> TieredMap<'a, K, V, RandomState>
     pub fn with_capacity(capacity: usize) -> Self {
         tm!(None, HashMap::with_capacity(capacity), 0, 0)
     }
+
+    /// Creates a root reserving room for `total` distinct keys across the
+    /// whole chain it will anchor.
+    ///
+    /// Only the root is allocated up front. Child scopes start empty and
+    /// allocate their own entries, including any that shadow the root, so
+    /// the reservation covers keys that end up defined at the root.
+    pub fn with_capacity_for_chain(total: usize) -> Self {
+        Self::with_capacity(total)
+    }
+
+    /// Builds a single-tier map from `pairs`, without naming the hasher as
+    /// `from_iter` requires. Later duplicates overwrite earlier ones.
+    pub fn from_pairs<T>(pairs: T) -> Self
+        where T: IntoIterator<Item = (K, V)>
+    {
+        tm!(None, HashMap::from_iter(pairs), 0, 0)
+    }
+
+    /// Builds a chain with one tier per group in `scopes`, outermost first,
+    /// and calls `f` with the innermost tier.
+    ///
+    /// Each tier borrows the one before it, so the chain only lives for the
+    /// duration of the call.
+    pub fn from_scopes<I, S, F, R>(scopes: I, f: F) -> R
+        where I: IntoIterator<Item = S>,
+              S: IntoIterator<Item = (K, V)>,
+              F: FnOnce(&TieredMap<'_, K, V, RandomState>) -> R
+    {
+        let mut scopes = scopes.into_iter();
+        let mut root = TieredMap::new();
+        if let Some(scope) = scopes.next() {
+            root.extend(scope);
+        }
+        root.build_scopes(&mut scopes, f)
+    }
+
+    /// Creates an empty map whose scopes keep a bloom filter of their
+    /// reachable keys, so most lookups of absent keys stop early instead of
+    /// walking the whole chain.
+    pub fn with_bloom() -> Self {
+        Self::with_hasher_and_bloom(Default::default())
+    }
 }
 
 impl<'a, K, V, H> TieredMap<'a, K, V, H>
@@ -53,315 +182,4255 @@ impl<'a, K, V, H> TieredMap<'a, K, V, H>
             0)
     }
 
+    /// Like `with_bloom`, hashing with `hash_builder`.
+    ///
+    /// The filter is a fixed 1024 bits with two probes per key, shared down
+    /// the chain. It pays off most for chains of up to a few hundred keys,
+    /// and less as more bits get set: by a few thousand keys it rarely
+    /// rejects one and only adds a hash per lookup.
+    pub fn with_hasher_and_bloom(hash_builder: H) -> Self {
+        let mut tm = Self::with_hasher(hash_builder);
+        tm.bloom = Some(Box::new(Bloom::new()));
+        tm
+    }
+
+    /// Caps the chain at `max_depth` tiers, as counted by `depth`, so that
+    /// runaway nesting fails in `new_scope` instead of overflowing later.
+    ///
+    /// The cap is inherited by every scope created below this one.
+    pub fn with_max_depth(mut self, max_depth: usize) -> Self {
+        self.scope_limit = Some(max_depth.saturating_sub(self.depth()));
+        self
+    }
+
+    fn child_scope_limit(&self) -> Result<Option<usize>, DepthLimitExceeded> {
+        match self.scope_limit {
+            None => Ok(None),
+            Some(0) => Err(DepthLimitExceeded),
+            Some(n) => Ok(Some(n - 1)),
+        }
+    }
+
+    /// Returns the underlying map of a parentless map, or `None` for a
+    /// child scope.
+    pub fn as_hashmap(&self) -> Option<&HashMap<K, V, H>> {
+        match self.parent {
+            None => Some(&self.map),
+            Some(_) => None,
+        }
+    }
+
     pub fn hasher(&self) -> &H {
         self.map.hasher()
     }
 
+    /// Returns the summed capacity of this tier and every linked parent.
+    ///
+    /// This is `capacity_local` added up over the chain, each tier counted
+    /// once. Parents can't grow while borrowed, so the sum stays accurate.
+    ///
+    /// Empty tiers skipped by `new_scope` are not linked, so their capacity
+    /// is not included.
     pub fn capacity(&self) -> usize {
-        self.parent_cap + self.map.capacity()
+        // deep chains of large tiers could overflow the sum
+        self.parent_cap.saturating_add(self.map.capacity())
     }
 
-    pub fn reserve(&mut self, additional: usize) {
-        self.map.reserve(additional);
+    /// Approximates the bytes allocated for entries and tombstones by this
+    /// tier and its parents, from their capacities.
+    pub fn memory_estimate(&self) -> usize {
+        iter::successors(Some(self), |t| t.parent)
+            .map(|t| {
+                t.map.capacity() * mem::size_of::<(K, V)>() +
+                t.removed.capacity() * mem::size_of::<K>()
+            })
+            .fold(0, usize::saturating_add)
     }
 
-    pub fn shrink_to_fit(&mut self) {
-        self.map.shrink_to_fit();
+    /// Returns the capacity of the current tier only.
+    pub fn capacity_local(&self) -> usize {
+        self.map.capacity()
     }
 
-    pub fn len(&self) -> usize {
-        self.parent_size + self.map.len()
+    pub fn reserve(&mut self, additional: usize) {
+        self.map.reserve(additional);
     }
 
-    pub fn get<Q: ?Sized>(&self, k: &Q) -> Option<&V>
-        where K: Borrow<Q>,
-              Q: Hash + Eq
-    {
-        self.map.get(k).or_else(|| self.parent.and_then(|parent| parent.get(k)))
+    pub fn try_reserve(&mut self, additional: usize) -> Result<(), TryReserveError> {
+        self.map.try_reserve(additional)
     }
 
-    pub fn contains_key<Q: ?Sized>(&self, k: &Q) -> bool
-        where K: Borrow<Q>,
-              Q: Hash + Eq
-    {
-        self.map.contains_key(k) ||
-        self.parent.map_or_else(|| false, |parent| parent.contains_key(k))
+    /// Reserves room for `additional` more entries in the current tier.
+    ///
+    /// This is an alias of `reserve`: `HashMap` sizes its table in whole
+    /// buckets and has no exact variant.
+    pub fn reserve_exact(&mut self, additional: usize) {
+        self.map.reserve(additional);
     }
 
-    pub fn insert(&mut self, k: K, v: V) -> Option<V> {
-        self.map.insert(k, v)
+    pub fn shrink_to_fit(&mut self) {
+        self.map.shrink_to_fit();
     }
 
-    pub fn iter(&self) -> Iter<K, V, H> {
-        Iter {
-            map: self,
-            iter: self.map.iter(),
-        }
+    /// Shrinks the current tier's capacity, keeping room for at least
+    /// `min_capacity` entries.
+    pub fn shrink_to(&mut self, min_capacity: usize) {
+        self.map.shrink_to(min_capacity);
     }
-}
-
-impl<'a, K, V, H> TieredMap<'a, K, V, H>
-    where K: Eq + Hash,
-          H: BuildHasher + Clone
-{
-    pub fn new_scope(&self) -> TieredMap<K, V, H> {
-        // skip empty tiers
-        if let Some(p) = self.parent {
-            if self.map.is_empty() {
-                return p.new_scope();
-            }
-        }
 
-        tm!(Some(self),
-            HashMap::with_hasher(self.map.hasher().clone()),
-            self.capacity(),
-            self.len())
+    pub fn len(&self) -> usize {
+        (self.parent_size - self.shadowed).saturating_add(self.map.len())
     }
-}
-
-#[derive(Clone)]
-pub struct Iter<'a, K: 'a, V: 'a, H: 'a> {
-    map: &'a TieredMap<'a, K, V, H>,
-    iter: hash_map::Iter<'a, K, V>,
-}
 
-impl<'a, K, V, H> Iterator for Iter<'a, K, V, H>
-    where K: Eq + Hash,
-          H: BuildHasher
-{
-    type Item = (&'a K, &'a V);
+    /// Returns `true` if neither this tier nor any parent tier holds an entry.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
 
-    fn next(&mut self) -> Option<Self::Item> {
-        match self.iter.next() {
-            None => {
-                // current iter is exhausted, move to next tier
-                match self.map.parent {
-                    None => None, // finished
-                    Some(p) => {
-                        self.map = p;
-                        self.iter = p.map.iter();
-                        self.iter.next()
-                    }
+    /// Counts the visible keys by walking every tier, instead of relying on
+    /// the bookkeeping behind `len`. Mostly useful for checking `len`.
+    pub fn effective_len(&self) -> usize {
+        // keys defined or removed by nearer tiers
+        let mut hidden = HashSet::new();
+        let mut len = 0;
+        let mut tier = self;
+        loop {
+            for k in tier.map.keys() {
+                if hidden.insert(k) {
+                    len += 1;
                 }
             }
-            s => s,
+            hidden.extend(tier.removed.iter());
+            match tier.parent {
+                Some(p) => tier = p,
+                None => return len,
+            }
         }
     }
 
-    #[inline]
-    fn size_hint(&self) -> (usize, Option<usize>) {
-        let l = self.map.parent.map_or(0, |t| t.len()) + self.iter.len();
-        (l, Some(l))
+    /// Returns the number of entries stored in the current tier only.
+    pub fn tier_len(&self) -> usize {
+        self.map.len()
     }
-}
 
-impl<'a, K, V, H> ExactSizeIterator for Iter<'a, K, V, H>
-    where K: Eq + Hash,
-          H: BuildHasher
-{
-    #[inline]
-    fn len(&self) -> usize {
-        self.size_hint().0
+    pub fn tier_is_empty(&self) -> bool {
+        self.map.is_empty()
     }
-}
 
-impl<'a, K, V, H> IntoIterator for &'a TieredMap<'a, K, V, H>
-    where K: Eq + Hash,
-          H: BuildHasher
-{
-    type Item = (&'a K, &'a V);
-    type IntoIter = Iter<'a, K, V, H>;
+    /// Returns the number of inherited keys removed in the current tier.
+    pub fn tombstone_count(&self) -> usize {
+        self.removed.len()
+    }
 
-    fn into_iter(self) -> Self::IntoIter {
-        self.iter()
+    /// Returns the identity of this tier, unique among the maps created in
+    /// this process. Contents aren't taken into account, unlike `==`.
+    pub fn id(&self) -> ScopeId {
+        self.id
     }
-}
 
-impl<'a, K, V, H> Clone for TieredMap<'a, K, V, H>
-    where K: Eq + Hash + Clone,
-          V: Clone,
-          H: BuildHasher + Clone
-{
-    fn clone(&self) -> Self {
-        tm!(self.parent.clone(),
-            self.map.clone(),
-            self.capacity(),
-            self.len())
+    pub fn parent(&self) -> Option<&'a TieredMap<'a, K, V, H>> {
+        self.parent
     }
-}
 
-impl<'a, K, V, H> Debug for TieredMap<'a, K, V, H>
-    where K: Eq + Hash + Debug,
-          V: Debug,
-          H: BuildHasher
-{
-    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
-        f.debug_map().entries(self.iter()).finish()
+    /// Returns the outermost tier, which is `self` for a parentless map.
+    pub fn root(&self) -> &TieredMap<'a, K, V, H> {
+        let mut tier = self;
+        while let Some(p) = tier.parent {
+            tier = p;
+        }
+        tier
     }
-}
 
-impl<'a, K, V, H> PartialEq for TieredMap<'a, K, V, H>
-    where K: Eq + Hash,
-          V: PartialEq,
-          H: BuildHasher
-{
-    fn eq(&self, other: &Self) -> bool {
-        self.len() == other.len() &&
-        self.iter().all(|(k, v)| other.get(k).map_or(false, |ov| *v == *ov))
+    /// Returns the number of linked tiers, including this one.
+    ///
+    /// Since `new_scope` links past empty tiers, this can be less than the
+    /// number of scopes created.
+    pub fn depth(&self) -> usize {
+        let mut depth = 1;
+        let mut tier = self;
+        while let Some(p) = tier.parent {
+            depth += 1;
+            tier = p;
+        }
+        depth
     }
-}
 
-impl<'a, K, V, H> Default for TieredMap<'a, K, V, H>
-    where K: Eq + Hash,
-          H: BuildHasher + Default
-{
-    fn default() -> Self {
-        Self::with_hasher(Default::default())
+    /// Returns the local entry count of each linked tier, current first.
+    pub fn scope_path(&self) -> Vec<usize> {
+        let mut path = vec![self.map.len()];
+        let mut tier = self;
+        while let Some(p) = tier.parent {
+            path.push(p.map.len());
+            tier = p;
+        }
+        path
     }
-}
 
-impl<'a, K, V, H, Q> Index<&'a Q> for TieredMap<'a, K, V, H>
-    where K: Eq + Hash + Borrow<Q>,
-          H: BuildHasher,
-          Q: Eq + Hash
-{
-    type Output = V;
+    /// Calls `f` with the depth and local map of each linked tier, current
+    /// first.
+    pub fn for_each_tier<F>(&self, mut f: F)
+        where F: FnMut(usize, &HashMap<K, V, H>)
+    {
+        for (depth, tier) in iter::successors(Some(self), |t| t.parent).enumerate() {
+            f(depth, &tier.map);
+        }
+    }
 
-    #[inline]
-    fn index(&self, index: &Q) -> &Self::Output {
-        self.get(index).expect("no entry found for key")
+    /// Pairs each linked tier's depth with its local entry count, current
+    /// first. Empty tiers skipped by `new_scope` aren't linked, so they never
+    /// appear; empty tiers that are linked do.
+    pub fn tier_histogram(&self) -> Vec<(usize, usize)> {
+        self.scope_path().into_iter().enumerate().collect()
     }
-}
 
+    pub fn get<Q>(&self, k: &Q) -> Option<&V>
+        where K: Borrow<Q>,
+              Q: ?Sized + Hash + Eq
+    {
+        self.get_key_value(k).map(|(_, v)| v)
+    }
 
-impl<'a, K, V, H> Eq for TieredMap<'a, K, V, H>
-    where K: Eq + Hash,
-          V: Eq,
-          H: BuildHasher
-{
-}
+    /// Like `get`, but wrapped in a `Cow` for callers that may need an owned
+    /// value. Visible values are always borrowed; `entry_cow` is the variant
+    /// that copies an inherited value into the current tier.
+    pub fn get_cow<Q>(&self, k: &Q) -> Option<Cow<'_, V>>
+        where K: Borrow<Q>,
+              Q: ?Sized + Hash + Eq,
+              V: Clone
+    {
+        self.get(k).map(Cow::Borrowed)
+    }
 
-impl<'a, K, V, H> FromIterator<(K, V)> for TieredMap<'a, K, V, H>
-    where K: Eq + Hash,
-          H: BuildHasher + Default
-{
-    fn from_iter<T>(iter: T) -> Self
-        where T: IntoIterator<Item = (K, V)>
+    /// Returns a copy of the visible value for `k`, or `V::default()` if no
+    /// tier defines it.
+    pub fn get_or_default<Q>(&self, k: &Q) -> V
+        where K: Borrow<Q>,
+              Q: ?Sized + Hash + Eq,
+              V: Clone + Default
     {
-        tm!(None, HashMap::from_iter(iter), 0, 0)
+        self.get(k).cloned().unwrap_or_default()
     }
-}
 
-impl<'a, K, V, H> Extend<(K, V)> for TieredMap<'a, K, V, H>
-    where K: Eq + Hash,
-          H: BuildHasher
-{
-    fn extend<T>(&mut self, iter: T)
-        where T: IntoIterator<Item = (K, V)>
+    /// Looks up each of `keys`, returning the results in the same order.
+    pub fn get_many<Q>(&self, keys: &[&Q]) -> Vec<Option<&V>>
+        where K: Borrow<Q>,
+              Q: ?Sized + Hash + Eq
     {
-        for (k, v) in iter {
-            self.insert(k, v);
-        }
+        keys.iter().map(|k| self.get(*k)).collect()
     }
-}
 
-impl<'a, K, V, H> Extend<(&'a K, &'a V)> for TieredMap<'a, K, V, H>
-    where K: Eq + Hash + Copy,
-          V: Copy,
-          H: BuildHasher
-{
-    fn extend<T>(&mut self, iter: T)
-        where T: IntoIterator<Item = (&'a K, &'a V)>
+    /// Like indexing, but the panic message includes the missing key.
+    ///
+    /// # Panics
+    ///
+    /// Panics if no tier defines `k`.
+    pub fn get_or_panic<Q>(&self, k: &Q) -> &V
+        where K: Borrow<Q>,
+              Q: ?Sized + Hash + Eq + Debug
     {
-        self.extend(iter.into_iter().map(|(&k, &v)| (k, v)));
+        match self.get(k) {
+            Some(v) => v,
+            None => panic!("no entry found for key {:?}", k),
+        }
     }
-}
 
-// TODO: quickcheck?
-#[cfg(test)]
-mod tests {
-    use std::collections::{HashSet, HashMap};
-    use std::collections::hash_map::RandomState;
-    use std::iter::FromIterator;
+    /// Like `get`, but a missing key is reported as an error carrying an
+    /// owned copy of it.
+    pub fn get_checked<Q>(&self, k: &Q) -> Result<&V, KeyNotFound<Q::Owned>>
+        where K: Borrow<Q>,
+              Q: ?Sized + Hash + Eq + ToOwned
+    {
+        self.get(k).ok_or_else(|| KeyNotFound { key: k.to_owned() })
+    }
 
-    use super::TieredMap;
+    /// Returns the stored key and value for `k` from the nearest tier
+    /// defining it.
+    pub fn get_key_value<Q>(&self, k: &Q) -> Option<(&K, &V)>
+        where K: Borrow<Q>,
+              Q: ?Sized + Hash + Eq
+    {
+        if self.bloom_rejects(k) {
+            return None;
+        }
 
-    #[test]
-    fn scopes() {
-        let mut tm1 = TieredMap::new();
-        {
-            let mut tm2 = tm1.new_scope();
+        let mut tier = self;
+        loop {
+            if let Some(kv) = tier.map.get_key_value(k) {
+                return Some(kv);
+            }
+            if tier.removed.contains(k) {
+                return None;
+            }
+            match tier.parent {
+                Some(p) => tier = p,
+                None => return None,
+            }
+        }
+    }
 
-            tm2.insert("a", 0);
+    // the nearest filter already covers every reachable key, so it is the
+    // only one worth checking
+    fn bloom_rejects<Q>(&self, k: &Q) -> bool
+        where K: Borrow<Q>,
+              Q: ?Sized + Hash + Eq
+    {
+        self.bloom.as_ref().is_some_and(|b| !b.may_contain(self.map.hasher().hash_one(k)))
+    }
 
-            assert_eq!(tm2.get("a"), Some(&0));
-            assert_eq!(tm1.get("a"), None);
+    /// Reports which tier, if any, provides the visible binding for `k`.
+    pub fn locate<Q>(&self, k: &Q) -> Location
+        where K: Borrow<Q>,
+              Q: ?Sized + Hash + Eq
+    {
+        if self.bloom_rejects(k) {
+            return Location::Absent;
         }
 
-        tm1.insert("a", 1);
-
-        let mut tm2 = tm1.new_scope();
+        let mut tier = self;
+        let mut depth = 0;
+        loop {
+            if tier.map.contains_key(k) {
+                return match depth {
+                    0 => Location::Local,
+                    d => Location::Inherited(d),
+                };
+            }
+            if tier.removed.contains(k) {
+                return Location::Absent;
+            }
+            match tier.parent {
+                Some(p) => tier = p,
+                None => return Location::Absent,
+            }
+            depth += 1;
+        }
+    }
 
-        tm2.insert("b", 2);
+    /// Returns the tier providing the visible binding for `k`, or `None` if
+    /// `k` isn't visible, such as after `remove`.
+    pub fn tier_of<Q>(&self, k: &Q) -> Option<&TieredMap<'a, K, V, H>>
+        where K: Borrow<Q>,
+              Q: ?Sized + Hash + Eq
+    {
+        let mut tier = self;
+        loop {
+            if tier.map.contains_key(k) {
+                return Some(tier);
+            }
+            if tier.removed.contains(k) {
+                return None;
+            }
+            match tier.parent {
+                Some(p) => tier = p,
+                None => return None,
+            }
+        }
+    }
 
-        assert_eq!(tm2.get("a"), Some(&1));
+    /// Returns the value for `k` from every tier that stores it, nearest
+    /// first, including values hidden by nearer tiers.
+    pub fn get_all<Q>(&self, k: &Q) -> Vec<&V>
+        where K: Borrow<Q>,
+              Q: ?Sized + Hash + Eq
+    {
+        let mut values = Vec::new();
+        let mut tier = self;
+        loop {
+            values.extend(tier.map.get(k));
+            match tier.parent {
+                Some(p) => tier = p,
+                None => return values,
+            }
+        }
+    }
 
-        let mut tm3 = tm2.new_scope();
+    /// Returns a mutable reference to the value for `k` in the current tier.
+    ///
+    /// Parent tiers are shared, so a key that is only inherited yields `None`.
+    pub fn get_mut<Q>(&mut self, k: &Q) -> Option<&mut V>
+        where K: Borrow<Q>,
+              Q: ?Sized + Hash + Eq
+    {
+        self.map.get_mut(k)
+    }
 
+    /// Returns mutable references to the values for several keys in the
+    /// current tier at once.
+    ///
+    /// Returns `None` if any key is missing locally or appears twice.
+    pub fn get_many_mut<Q, const N: usize>(&mut self, keys: [&Q; N]) -> Option<[&mut V; N]>
+        where K: Borrow<Q>,
+              Q: ?Sized + Hash + Eq
+    {
+        for (i, k) in keys.iter().enumerate() {
+            if keys[i + 1..].contains(k) {
+                return None;
+            }
+        }
+
+        #[cfg(not(feature = "no_std"))]
+        let values = self.map.get_disjoint_mut(keys);
+        #[cfg(feature = "no_std")]
+        let values = self.map.get_many_mut(keys);
+        if values.iter().any(Option::is_none) {
+            return None;
+        }
+        Some(values.map(Option::unwrap))
+    }
+
+    /// Returns the value for `k` only if it is defined in the current tier.
+    pub fn get_local<Q>(&self, k: &Q) -> Option<&V>
+        where K: Borrow<Q>,
+              Q: ?Sized + Hash + Eq
+    {
+        self.map.get(k)
+    }
+
+    pub fn contains_key<Q>(&self, k: &Q) -> bool
+        where K: Borrow<Q>,
+              Q: ?Sized + Hash + Eq
+    {
+        self.get_key_value(k).is_some()
+    }
+
+    /// Returns `true` if every key in `keys` is visible, stopping at the
+    /// first missing one.
+    pub fn contains_all<Q>(&self, keys: &[Q]) -> bool
+        where K: Borrow<Q>,
+              Q: Hash + Eq
+    {
+        keys.iter().all(|k| self.contains_key(k))
+    }
+
+    /// Returns `true` if any key in `keys` is visible, stopping at the first
+    /// one found.
+    pub fn contains_any<Q>(&self, keys: &[Q]) -> bool
+        where K: Borrow<Q>,
+              Q: Hash + Eq
+    {
+        keys.iter().any(|k| self.contains_key(k))
+    }
+
+    pub fn contains_key_local<Q>(&self, k: &Q) -> bool
+        where K: Borrow<Q>,
+              Q: ?Sized + Hash + Eq
+    {
+        self.map.contains_key(k)
+    }
+
+    /// Returns `true` if the tier `depth` steps up the chain, where 0 is the
+    /// current tier, defines `k` itself.
+    pub fn contains_key_at_depth<Q>(&self, k: &Q, depth: usize) -> bool
+        where K: Borrow<Q>,
+              Q: ?Sized + Hash + Eq
+    {
+        self.tier_at(depth).is_some_and(|t| t.map.contains_key(k))
+    }
+
+    /// Iterates over the keys stored in the tier `depth` steps up the chain,
+    /// or returns `None` if the chain is shorter than that.
+    pub fn keys_at_depth(&self, depth: usize) -> Option<hash_map::Keys<'_, K, V>> {
+        self.tier_at(depth).map(|t| t.map.keys())
+    }
+
+    pub fn values_at_depth(&self, depth: usize) -> Option<hash_map::Values<'_, K, V>> {
+        self.tier_at(depth).map(|t| t.map.values())
+    }
+
+    fn tier_at(&self, depth: usize) -> Option<&TieredMap<'a, K, V, H>> {
+        iter::successors(Some(self), |t| t.parent).nth(depth)
+    }
+
+    /// Returns `true` if `k` is defined locally and also visible in a parent.
+    pub fn shadows<Q>(&self, k: &Q) -> bool
+        where K: Borrow<Q>,
+              Q: ?Sized + Hash + Eq
+    {
+        self.shadowed_value(k).is_some()
+    }
+
+    /// Returns `true` if any visible entry has the value `v`. Values hidden
+    /// by nearer tiers are not considered.
+    pub fn contains_value(&self, v: &V) -> bool
+        where V: PartialEq
+    {
+        self.values().any(|x| x == v)
+    }
+
+    /// Returns a visible key whose value is `v`.
+    ///
+    /// Tiers are searched nearest first; among several matching keys in one
+    /// tier, which is returned is unspecified.
+    pub fn find_key_by_value(&self, v: &V) -> Option<&K>
+        where V: PartialEq
+    {
+        self.iter().find(|&(_, x)| x == v).map(|(k, _)| k)
+    }
+
+    /// Iterates over the visible keys of `self` that aren't visible in `other`.
+    pub fn difference<'b>(&'b self,
+                          other: &'b TieredMap<'b, K, V, H>)
+                          -> impl Iterator<Item = &'b K> + 'b {
+        self.keys().filter(move |k| !other.contains_key(*k))
+    }
+
+    /// Iterates over the keys visible in both `self` and `other`.
+    pub fn intersection<'b>(&'b self,
+                            other: &'b TieredMap<'b, K, V, H>)
+                            -> impl Iterator<Item = &'b K> + 'b {
+        self.keys().filter(move |k| other.contains_key(*k))
+    }
+
+    /// Returns `true` if every key visible in `self` is visible in `other`.
+    pub fn is_subset(&self, other: &TieredMap<K, V, H>) -> bool {
+        self.len() <= other.len() && self.keys().all(|k| other.contains_key(k))
+    }
+
+    pub fn is_superset(&self, other: &TieredMap<K, V, H>) -> bool {
+        other.is_subset(self)
+    }
+
+    /// Like `is_subset`, but also requires the visible values to match.
+    pub fn is_submap(&self, other: &TieredMap<K, V, H>) -> bool
+        where V: PartialEq
+    {
+        self.len() <= other.len() && self.iter().all(|(k, v)| other.get(k) == Some(v))
+    }
+
+    /// Returns the inherited value hidden by the local binding for `k`.
+    pub fn shadowed_value<Q>(&self, k: &Q) -> Option<&V>
+        where K: Borrow<Q>,
+              Q: ?Sized + Hash + Eq
+    {
+        if !self.map.contains_key(k) {
+            return None;
+        }
+        self.parent.and_then(|p| p.get(k))
+    }
+
+    pub fn insert(&mut self, k: K, v: V) -> Option<V> {
+        match self.entry(k) {
+            Entry::Occupied(mut e) => Some(e.insert(v)),
+            Entry::Vacant(e) => {
+                e.insert(v);
+                None
+            }
+        }
+    }
+
+    /// Inserts into the current tier, returning a reference to the stored
+    /// value instead of the replaced one.
+    pub fn insert_ref(&mut self, k: K, v: V) -> &mut V {
+        match self.entry(k) {
+            Entry::Occupied(mut e) => {
+                e.insert(v);
+                e.into_mut()
+            }
+            Entry::Vacant(e) => e.insert(v),
+        }
+    }
+
+    /// Inserts every pair into the current tier, returning how many keys were
+    /// not already defined locally.
+    pub fn extend_counting<T>(&mut self, iter: T) -> usize
+        where T: IntoIterator<Item = (K, V)>
+    {
+        let mut new = 0;
+        for (k, v) in iter {
+            if self.insert(k, v).is_none() {
+                new += 1;
+            }
+        }
+        new
+    }
+
+    /// Moves the local entries of `other` into the current tier, overwriting
+    /// on conflict, and leaves `other`'s local tier empty.
+    ///
+    /// Only `other`'s local tier is moved: its parents are untouched and its
+    /// tombstones stay with it.
+    pub fn append(&mut self, other: &mut TieredMap<K, V, H>) {
+        self.extend(other.drain());
+    }
+
+    /// Inserts clones of the entries visible in `other` into the current tier.
+    pub fn extend_from(&mut self, other: &TieredMap<K, V, H>)
+        where K: Clone,
+              V: Clone
+    {
+        self.extend(other.iter().map(|(k, v)| (k.clone(), v.clone())));
+    }
+
+    /// Inserts each pair whose key isn't visible in any tier yet, leaving
+    /// existing bindings unshadowed.
+    pub fn extend_if_absent<T>(&mut self, iter: T)
+        where T: IntoIterator<Item = (K, V)>
+    {
+        for (k, v) in iter {
+            if !self.contains_key(&k) {
+                self.entry(k).or_insert(v);
+            }
+        }
+    }
+
+    /// Inserts into the current tier unless `k` is visible in any tier, in
+    /// which case the rejected pair is returned.
+    pub fn try_insert(&mut self, k: K, v: V) -> Result<&V, OccupiedError<K, V>> {
+        if self.contains_key(&k) {
+            return Err(OccupiedError { key: k, value: v });
+        }
+        Ok(self.entry(k).or_insert(v))
+    }
+
+    /// Inserts into the current tier unless `k` is already defined locally.
+    pub fn try_insert_local(&mut self, k: K, v: V) -> Result<&V, OccupiedError<K, V>> {
+        if self.contains_key_local(&k) {
+            return Err(OccupiedError { key: k, value: v });
+        }
+        Ok(self.entry(k).or_insert(v))
+    }
+
+    /// Returns the visible value for `k`, or inserts `f()` into the current
+    /// tier if no tier defines it.
+    pub fn get_or_insert_with<F>(&mut self, k: K, f: F) -> &V
+        where F: FnOnce() -> V
+    {
+        match self.entry(k) {
+            Entry::Occupied(e) => e.into_mut(),
+            Entry::Vacant(e) => {
+                match e.inherited_value() {
+                    Some(v) => v,
+                    None => e.insert(f()),
+                }
+            }
+        }
+    }
+
+    /// Returns a mutable reference to the local value for `k`, first copying
+    /// an inherited value down into the current tier if needed. Returns
+    /// `None` if no tier defines `k`.
+    pub fn entry_cow(&mut self, k: K) -> Option<&mut V>
+        where V: Clone
+    {
+        match self.entry(k) {
+            Entry::Occupied(e) => Some(e.into_mut()),
+            Entry::Vacant(e) => {
+                match e.inherited_value() {
+                    Some(v) => Some(e.insert(v.clone())),
+                    None => None,
+                }
+            }
+        }
+    }
+
+    /// Like `entry_cow`, but inserts `default` locally if no tier defines `k`.
+    pub fn entry_cow_or(&mut self, k: K, default: V) -> &mut V
+        where V: Clone
+    {
+        match self.entry(k) {
+            Entry::Occupied(e) => e.into_mut(),
+            Entry::Vacant(e) => {
+                let v = e.inherited_value().cloned().unwrap_or(default);
+                e.insert(v)
+            }
+        }
+    }
+
+    /// Like `entry_cow_or`, inserting `V::default()` if no tier defines `k`.
+    pub fn entry_or_default(&mut self, k: K) -> &mut V
+        where V: Clone + Default
+    {
+        self.entry_cow_or(k, V::default())
+    }
+
+    /// Gets the entry for `k` in the current tier.
+    ///
+    /// The entry is occupied only if `k` is stored locally, so a key that is
+    /// only inherited yields a vacant entry.
+    pub fn entry(&mut self, k: K) -> Entry<'_, K, V, H> {
+        let hash = self.bloom.as_ref().map(|_| self.map.hasher().hash_one(&k));
+
+        match self.map.entry(k) {
+            hash_map::Entry::Occupied(inner) => {
+                Entry::Occupied(OccupiedEntry { inner, parent: self.parent })
+            }
+            hash_map::Entry::Vacant(inner) => {
+                Entry::Vacant(VacantEntry {
+                    inner,
+                    parent: self.parent,
+                    removed: &mut self.removed,
+                    shadowed: &mut self.shadowed,
+                    bloom: self.bloom.as_deref_mut().and_then(|b| hash.map(|h| (b, h))),
+                })
+            }
+        }
+    }
+
+    /// Removes `k` from the current tier, returning its local value.
+    ///
+    /// Removing a local binding uncovers any inherited one. A key that is
+    /// only inherited is hidden from this tier onwards instead, leaving the
+    /// parent untouched, and `None` is returned.
+    pub fn remove<Q>(&mut self, k: &Q) -> Option<V>
+        where K: Borrow<Q> + Clone,
+              Q: ?Sized + Hash + Eq
+    {
+        if let Some(v) = self.map.remove(k) {
+            if self.parent.is_some_and(|p| p.contains_key(k)) {
+                self.shadowed -= 1;
+            }
+            return Some(v);
+        }
+
+        if !self.removed.contains(k) {
+            if let Some((pk, _)) = self.parent.and_then(|p| p.get_key_value(k)) {
+                self.removed.insert(pk.clone());
+                self.shadowed += 1;
+            }
+        }
+
+        None
+    }
+
+    // like `remove`, for a key owned by the caller, which can become the
+    // tombstone itself
+    fn remove_owned(&mut self, k: K) {
+        if self.map.remove(&k).is_some() {
+            if self.parent.is_some_and(|p| p.contains_key(&k)) {
+                self.shadowed -= 1;
+            }
+        } else if !self.removed.contains(&k) &&
+                  self.parent.is_some_and(|p| p.contains_key(&k)) {
+            self.removed.insert(k);
+            self.shadowed += 1;
+        }
+    }
+
+    /// Records which keys the current tier defines and removes, for a later
+    /// `rollback`.
+    pub fn checkpoint(&self) -> Checkpoint<K>
+        where K: Clone
+    {
+        Checkpoint {
+            id: self.id,
+            keys: self.map.keys().cloned().collect(),
+            removed: self.removed.clone(),
+        }
+    }
+
+    /// Undoes the insertions and removals of keys since `cp` was taken.
+    ///
+    /// Keys inserted since are dropped again and removed inherited keys
+    /// become visible again. Values are not restored, so a key that existed
+    /// at the checkpoint keeps any later overwrite, and a local entry removed
+    /// since stays removed.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `cp` was taken from another tier, clones included.
+    pub fn rollback(&mut self, cp: Checkpoint<K>) {
+        assert!(cp.id == self.id,
+                "checkpoint taken from tier {:?} rolled back on tier {:?}",
+                cp.id,
+                self.id);
+        self.retain(|k, _| cp.keys.contains(k));
+
+        let parent = self.parent;
+        let shadowed = &mut self.shadowed;
+        self.removed.retain(|k| {
+            let keep = cp.removed.contains(k);
+            if !keep && parent.is_some_and(|p| p.contains_key(k)) {
+                *shadowed -= 1;
+            }
+            keep
+        });
+
+        // tombstones cleared by inserting, then dropped again above
+        for k in cp.removed {
+            if !self.removed.contains(&k) && !self.map.contains_key(&k) {
+                if self.parent.is_some_and(|p| p.contains_key(&k)) {
+                    self.shadowed += 1;
+                }
+                self.removed.insert(k);
+            }
+        }
+    }
+
+    /// Retains only the local entries for which `f` returns `true`.
+    ///
+    /// Parent tiers are left alone, so dropping a local binding uncovers any
+    /// inherited one.
+    pub fn retain<F>(&mut self, mut f: F)
+        where F: FnMut(&K, &mut V) -> bool
+    {
+        let parent = self.parent;
+        let shadowed = &mut self.shadowed;
+
+        self.map.retain(|k, v| {
+            let keep = f(k, v);
+            if !keep && parent.is_some_and(|p| p.contains_key(k)) {
+                *shadowed -= 1;
+            }
+            keep
+        });
+    }
+
+    /// Removes every local entry and tombstone from the current tier.
+    ///
+    /// Parent tiers are not affected, so all inherited entries become
+    /// visible again.
+    pub fn clear(&mut self) {
+        self.map.clear();
+        self.removed.clear();
+        self.shadowed = 0;
+    }
+
+    /// Removes and yields the local entries, uncovering any inherited ones.
+    pub fn drain(&mut self) -> hash_map::Drain<'_, K, V> {
+        if let Some(p) = self.parent {
+            self.shadowed -= self.map.keys().filter(|k| p.contains_key(*k)).count();
+        }
+        self.map.drain()
+    }
+
+    /// Moves the local entries into a new root map, uncovering any inherited
+    /// ones. Tombstones stay behind.
+    pub fn split_off_local(&mut self) -> TieredMap<'static, K, V, H>
+        where H: Default
+    {
+        let mut map = HashMap::with_capacity_and_hasher(self.map.len(), H::default());
+        map.extend(self.drain());
+        TieredMap::from(map)
+    }
+
+    /// Relinks this tier's local entries and tombstones onto `parent`.
+    ///
+    /// Rebasing is not checked against depth caps, so the result can sit
+    /// deeper than this tier's or `parent`'s cap allows. Scopes created
+    /// below it are held to the tighter of the two caps.
+    pub fn with_parent<'b>(self, parent: &'b TieredMap<'b, K, V, H>) -> TieredMap<'b, K, V, H> {
+        // a cap counts tiers from the root, which is now `parent`'s root
+        let own_limit = self.scope_limit
+            .map(|l| (l + self.depth()).saturating_sub(parent.depth() + 1));
+        let parent_limit = parent.scope_limit.map(|l| l.saturating_sub(1));
+        let scope_limit = own_limit.into_iter().chain(parent_limit).min();
+
+        let shadowed = self.map.keys().chain(self.removed.iter())
+            .filter(|k| parent.contains_key(*k))
+            .count();
+
+        // the old filter summarized the old parent's keys
+        let bloom = self.bloom.as_ref().map(|_| {
+            let mut bloom = Box::new(Bloom::new());
+            for k in self.map.keys().chain(parent.keys()) {
+                bloom.insert(self.map.hasher().hash_one(k));
+            }
+            bloom
+        });
+
+        TieredMap {
+            parent: Some(parent),
+            map: self.map,
+            parent_cap: parent.capacity(),
+            parent_size: parent.len(),
+            shadowed,
+            removed: self.removed,
+            bloom,
+            id: self.id,
+            scope_limit,
+        }
+    }
+
+    /// Relinks this tier past any empty tiers directly above it, which
+    /// `new_scope` would have skipped but `with_parent` keeps.
+    ///
+    /// Parents are borrowed, so empty tiers farther up, above the nearest
+    /// non-empty ancestor, stay linked. A depth cap from `with_max_depth`
+    /// applies to the shortened chain, leaving room for the pruned tiers.
+    pub fn prune_empty(mut self) -> TieredMap<'a, K, V, H> {
+        if let Some(mut parent) = self.parent {
+            let mut pruned = 0;
+            while parent.map.is_empty() && parent.removed.is_empty() {
+                match parent.parent {
+                    Some(p) => parent = p,
+                    None => break,
+                }
+                pruned += 1;
+            }
+            // an empty tier sees exactly what its parent sees, so only the
+            // capacity and depth change
+            self.parent = Some(parent);
+            self.parent_cap = parent.capacity();
+            self.scope_limit = self.scope_limit.map(|l| l + pruned);
+        }
+        self
+    }
+
+    /// Drops the tombstones for keys the parent chain no longer defines,
+    /// such as after rebasing with `with_parent`.
+    pub fn compact(&mut self) {
+        match self.parent {
+            // such tombstones were never counted as shadowing anything
+            Some(p) => self.removed.retain(|k| p.contains_key(k)),
+            None => self.removed.clear(),
+        }
+        self.removed.shrink_to_fit();
+    }
+
+    /// Consumes this tier, returning only its local entries.
+    ///
+    /// This ends the borrow of the parent, so a finished scope can be merged
+    /// upward with `parent.extend(child.into_local())`. Tombstones are
+    /// discarded, so removals are not carried over.
+    pub fn into_local(self) -> HashMap<K, V, H> {
+        self.map
+    }
+
+    /// Creates a child scope hashing with `hash_builder`, for hashers that
+    /// can't be cloned from the parent.
+    ///
+    /// Every tier hashes its own entries, so lookups stay correct with a
+    /// different hasher, but the child doesn't keep a bloom filter.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the depth cap set by `with_max_depth` would be exceeded.
+    pub fn new_scope_with_hasher(&self, hash_builder: H) -> TieredMap<'_, K, V, H> {
+        self.try_scope(hash_builder, 0).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    // every scope constructor goes through here, so that skipping empty
+    // tiers and the depth cap stay consistent
+    fn try_scope(&self, hash_builder: H, capacity: usize)
+        -> Result<TieredMap<'_, K, V, H>, DepthLimitExceeded>
+    {
+        // skip empty tiers
+        if let Some(p) = self.parent {
+            if self.map.is_empty() && self.removed.is_empty() {
+                return p.try_scope(hash_builder, capacity);
+            }
+        }
+
+        let mut tm = tm!(Some(self),
+                         HashMap::with_capacity_and_hasher(capacity, hash_builder),
+                         self.capacity(),
+                         self.len());
+        tm.scope_limit = self.child_scope_limit()?;
+        Ok(tm)
+    }
+
+    pub fn iter(&self) -> Iter<'_, K, V, H> {
+        Iter::new(self)
+    }
+
+    /// Collects the visible entries into a `Vec`, in no particular order.
+    pub fn to_vec(&self) -> Vec<(&K, &V)> {
+        self.iter().collect()
+    }
+
+    /// Collects the visible entries into a `Vec` sorted by key, for output
+    /// that doesn't depend on hashing or tiering.
+    pub fn iter_sorted(&self) -> Vec<(&K, &V)>
+        where K: Ord
+    {
+        let mut entries = self.to_vec();
+        entries.sort_unstable_by(|a, b| a.0.cmp(b.0));
+        entries
+    }
+
+    /// Like `iter`, but also yields the depth of the tier each entry lives
+    /// in, where 0 is the current tier.
+    pub fn iter_with_tier(&self) -> impl Iterator<Item = (&K, &V, usize)> {
+        let mut iter = self.iter();
+        iter::from_fn(move || iter.next_with_tier())
+    }
+
+    /// Yields every tier's local entries, shadowed ones included, tier by
+    /// tier from the root down to the current tier.
+    ///
+    /// Depths count up the chain from the current tier at 0, so they
+    /// decrease along the iteration.
+    pub fn iter_by_tier(&self) -> impl Iterator<Item = (usize, &K, &V)> {
+        let tiers = iter::successors(Some(self), |t| t.parent).collect::<Vec<_>>();
+        tiers.into_iter()
+            .enumerate()
+            .rev()
+            .flat_map(|(depth, tier)| tier.map.iter().map(move |(k, v)| (depth, k, v)))
+    }
+
+    /// Yields the entries of parent tiers that are hidden from this tier,
+    /// either by a nearer binding or a tombstone, with the depth of the tier
+    /// each lives in.
+    pub fn iter_shadowed(&self) -> impl Iterator<Item = (&K, &V, usize)> {
+        iter::successors(self.parent, |t| t.parent)
+            .enumerate()
+            .flat_map(move |(i, tier)| {
+                let depth = i + 1;
+                tier.map.iter()
+                    .filter(move |&(k, _)| {
+                        iter::successors(Some(self), |t| t.parent)
+                            .take(depth)
+                            .any(|t| t.map.contains_key(k) || t.removed.contains(k))
+                    })
+                    .map(move |(k, v)| (k, v, depth))
+            })
+    }
+
+    /// Iterates over the entries stored in the current tier only.
+    pub fn iter_local(&self) -> hash_map::Iter<'_, K, V> {
+        self.map.iter()
+    }
+
+    /// Returns a wrapper whose `Debug` output lists each tier's local
+    /// entries separately, starting with the current tier at depth 0.
+    pub fn debug_tiers(&self) -> DebugTiers<'_, K, V, H> {
+        DebugTiers { map: self }
+    }
+
+    pub fn keys(&self) -> Keys<'_, K, V, H> {
+        Keys { inner: self.iter() }
+    }
+
+    pub fn values(&self) -> Values<'_, K, V, H> {
+        Values { inner: self.iter() }
+    }
+
+    /// Iterates mutably over the values stored in the current tier only.
+    pub fn values_mut(&mut self) -> hash_map::ValuesMut<'_, K, V> {
+        self.map.values_mut()
+    }
+}
+
+impl<'a, K, V, H> TieredMap<'a, K, V, H>
+    where K: Eq + Hash,
+          H: BuildHasher + Clone
+{
+    /// # Panics
+    ///
+    /// Panics if the depth cap set by `with_max_depth` would be exceeded.
+    pub fn new_scope(&self) -> TieredMap<'_, K, V, H> {
+        self.new_scope_with_capacity(0)
+    }
+
+    /// Like `new_scope`, but reports hitting the depth cap as an error.
+    pub fn try_new_scope(&self) -> Result<TieredMap<'_, K, V, H>, DepthLimitExceeded> {
+        self.try_new_scope_with_capacity(0)
+    }
+
+    /// # Panics
+    ///
+    /// Panics if the depth cap set by `with_max_depth` would be exceeded.
+    pub fn new_scope_with_capacity(&self, capacity: usize) -> TieredMap<'_, K, V, H> {
+        self.try_new_scope_with_capacity(capacity).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    fn try_new_scope_with_capacity(&self, capacity: usize)
+        -> Result<TieredMap<'_, K, V, H>, DepthLimitExceeded>
+    {
+        let mut tm = self.try_scope(self.map.hasher().clone(), capacity)?;
+        // copy the filter of the tier actually linked
+        tm.bloom = tm.parent.and_then(|p| p.bloom.clone());
+        Ok(tm)
+    }
+
+    /// Opens a child scope that holds this map mutably, so that its bindings
+    /// can be merged back into this tier when the guard is dropped.
+    ///
+    /// Merging also removes from this tier the bindings the scope removed.
+    /// With `merge` unset the bindings are discarded instead.
+    pub fn scope_guard(&mut self, merge: bool) -> ScopeGuard<'_, 'a, K, V, H> {
+        let local = HashMap::with_hasher(self.map.hasher().clone());
+        ScopeGuard { parent: self, local, removed: HashSet::new(), merge }
+    }
+
+    fn build_scopes<I, S, F, R>(&self, scopes: &mut I, f: F) -> R
+        where I: Iterator<Item = S>,
+              S: IntoIterator<Item = (K, V)>,
+              F: FnOnce(&TieredMap<'_, K, V, H>) -> R
+    {
+        match scopes.next() {
+            None => f(self),
+            Some(scope) => {
+                let mut tier = self.new_scope();
+                tier.extend(scope);
+                tier.build_scopes(scopes, f)
+            }
+        }
+    }
+
+    /// Collects the visible entries into a flat `HashMap`.
+    pub fn to_hashmap(&self) -> HashMap<K, V, H>
+        where K: Clone,
+              V: Clone
+    {
+        let mut map = HashMap::with_capacity_and_hasher(self.len(), self.hasher().clone());
+        map.extend(self.iter().map(|(k, v)| (k.clone(), v.clone())));
+        map
+    }
+
+    /// Collects the visible keys into a `HashSet` sharing this map's hasher.
+    pub fn visible_key_set(&self) -> HashSet<K, H>
+        where K: Clone
+    {
+        let mut set = HashSet::with_capacity_and_hasher(self.len(), self.hasher().clone());
+        set.extend(self.keys().cloned());
+        set
+    }
+
+    /// Collapses the visible entries into a new single-tier map.
+    ///
+    /// The result doesn't borrow any tier, so it can outlive the scope chain.
+    pub fn flatten(&self) -> TieredMap<'static, K, V, H>
+        where K: Clone,
+              V: Clone
+    {
+        TieredMap::from(self.to_hashmap())
+    }
+
+    /// Copies every tier of the chain into a new `SharedTieredMap`, keeping
+    /// the layering and tombstones but none of the borrows.
+    pub fn deep_clone(&self) -> SharedTieredMap<K, V, H>
+        where K: Clone,
+              V: Clone
+    {
+        let mut tiers = iter::successors(Some(self), |t| t.parent).collect::<Vec<_>>();
+        let tier = tiers.remove(0);
+
+        // rebuild from the root down
+        let parent = tiers.into_iter().rev().fold(None, |parent, t| {
+            Some(Rc::new(t.deep_clone_tier(parent)))
+        });
+        tier.deep_clone_tier(parent)
+    }
+
+    fn deep_clone_tier(&self, parent: Option<Rc<SharedTieredMap<K, V, H>>>)
+        -> SharedTieredMap<K, V, H>
+        where K: Clone,
+              V: Clone
+    {
+        SharedTieredMap::from_parts(parent,
+                                    self.map.clone(),
+                                    self.removed.clone(),
+                                    self.parent_size,
+                                    self.shadowed)
+    }
+
+    /// Flattens the visible entries passing `pred` into a new single-tier map.
+    ///
+    /// Unlike `retain`, this considers every tier.
+    pub fn filtered<F>(&self, mut pred: F) -> TieredMap<'static, K, V, H>
+        where K: Clone,
+              V: Clone,
+              F: FnMut(&K, &V) -> bool
+    {
+        let mut map = HashMap::with_hasher(self.hasher().clone());
+        map.extend(self.iter()
+            .filter(|&(k, v)| pred(k, v))
+            .map(|(k, v)| (k.clone(), v.clone())));
+        TieredMap::from(map)
+    }
+
+    /// Flattens the visible entries into a new single-tier map, transforming
+    /// each value with `f`.
+    pub fn map_values<W, F>(&self, mut f: F) -> TieredMap<'static, K, W, H>
+        where K: Clone,
+              F: FnMut(&V) -> W
+    {
+        let mut map = HashMap::with_capacity_and_hasher(self.len(), self.hasher().clone());
+        map.extend(self.iter().map(|(k, v)| (k.clone(), f(v))));
+        TieredMap::from(map)
+    }
+}
+
+/// Flattens the visible entries of every map in `maps` into one root map.
+///
+/// Maps are merged in order, so on a key present in several the value from
+/// the last one wins.
+pub fn merge_all<K, V, H>(maps: &[&TieredMap<K, V, H>]) -> TieredMap<'static, K, V, H>
+    where K: Eq + Hash + Clone,
+          V: Clone,
+          H: BuildHasher + Default
+{
+    let mut merged = TieredMap::with_hasher(H::default());
+    for map in maps {
+        merged.extend_from(map);
+    }
+    merged
+}
+
+#[derive(Clone)]
+pub struct Iter<'a, K: 'a, V: 'a, H: 'a> {
+    inner: IterInner<'a, K, V, H>,
+}
+
+#[derive(Clone)]
+enum IterInner<'a, K: 'a, V: 'a, H: 'a> {
+    // a parentless map has no other tier to deduplicate against
+    Root(hash_map::Iter<'a, K, V>),
+    Tiers(Tiers<'a, K, V, H>),
+}
+
+#[derive(Clone)]
+struct Tiers<'a, K: 'a, V: 'a, H: 'a> {
+    map: &'a TieredMap<'a, K, V, H>,
+    iter: hash_map::Iter<'a, K, V>,
+    // tiers walked past so far
+    depth: usize,
+    // keys yielded from nearer tiers, which hide any farther entries
+    seen: HashSet<&'a K>,
+    remaining: usize,
+}
+
+impl<'a, K, V, H> Iter<'a, K, V, H>
+    where K: Eq + Hash,
+          H: BuildHasher
+{
+    fn new(map: &'a TieredMap<'a, K, V, H>) -> Self {
+        let inner = match map.parent {
+            None => IterInner::Root(map.map.iter()),
+            Some(_) => {
+                IterInner::Tiers(Tiers {
+                    map,
+                    iter: map.map.iter(),
+                    depth: 0,
+                    seen: HashSet::new(),
+                    remaining: map.len(),
+                })
+            }
+        };
+        Iter { inner }
+    }
+
+    fn next_with_tier(&mut self) -> Option<(&'a K, &'a V, usize)> {
+        match self.inner {
+            IterInner::Root(ref mut iter) => iter.next().map(|(k, v)| (k, v, 0)),
+            IterInner::Tiers(ref mut tiers) => tiers.next(),
+        }
+    }
+}
+
+impl<'a, K, V, H> Tiers<'a, K, V, H>
+    where K: Eq + Hash,
+          H: BuildHasher
+{
+    fn next(&mut self) -> Option<(&'a K, &'a V, usize)> {
+        loop {
+            match self.iter.next() {
+                Some((k, v)) => {
+                    if self.seen.contains(k) {
+                        continue; // shadowed
+                    }
+                    // no need to remember keys of the last tier
+                    if self.map.parent.is_some() {
+                        self.seen.insert(k);
+                    }
+                    self.remaining -= 1;
+                    return Some((k, v, self.depth));
+                }
+                None => {
+                    // current iter is exhausted, move to next tier
+                    match self.map.parent {
+                        None => return None, // finished
+                        Some(p) => {
+                            self.seen.extend(self.map.removed.iter());
+                            self.map = p;
+                            self.iter = p.map.iter();
+                            self.depth += 1;
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl<'a, K, V, H> Iterator for Iter<'a, K, V, H>
+    where K: Eq + Hash,
+          H: BuildHasher
+{
+    type Item = (&'a K, &'a V);
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.inner {
+            IterInner::Root(ref mut iter) => iter.next(),
+            IterInner::Tiers(ref mut tiers) => tiers.next().map(|(k, v, _)| (k, v)),
+        }
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        match self.inner {
+            IterInner::Root(ref iter) => iter.size_hint(),
+            IterInner::Tiers(ref tiers) => (tiers.remaining, Some(tiers.remaining)),
+        }
+    }
+
+    fn fold<B, F>(self, init: B, mut f: F) -> B
+        where F: FnMut(B, Self::Item) -> B
+    {
+        match self.inner {
+            IterInner::Root(iter) => iter.fold(init, f),
+            IterInner::Tiers(mut tiers) => {
+                let mut acc = init;
+                while let Some((k, v, _)) = tiers.next() {
+                    acc = f(acc, (k, v));
+                }
+                acc
+            }
+        }
+    }
+}
+
+impl<'a, K, V, H> ExactSizeIterator for Iter<'a, K, V, H>
+    where K: Eq + Hash,
+          H: BuildHasher
+{
+    #[inline]
+    fn len(&self) -> usize {
+        self.size_hint().0
+    }
+}
+
+#[derive(Clone)]
+pub struct Keys<'a, K: 'a, V: 'a, H: 'a> {
+    inner: Iter<'a, K, V, H>,
+}
+
+impl<'a, K, V, H> Iterator for Keys<'a, K, V, H>
+    where K: Eq + Hash,
+          H: BuildHasher
+{
+    type Item = &'a K;
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next().map(|(k, _)| k)
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+
+    fn fold<B, F>(self, init: B, mut f: F) -> B
+        where F: FnMut(B, Self::Item) -> B
+    {
+        self.inner.fold(init, |acc, (k, _)| f(acc, k))
+    }
+}
+
+impl<'a, K, V, H> ExactSizeIterator for Keys<'a, K, V, H>
+    where K: Eq + Hash,
+          H: BuildHasher
+{
+    #[inline]
+    fn len(&self) -> usize {
+        self.inner.len()
+    }
+}
+
+#[derive(Clone)]
+pub struct Values<'a, K: 'a, V: 'a, H: 'a> {
+    inner: Iter<'a, K, V, H>,
+}
+
+impl<'a, K, V, H> Iterator for Values<'a, K, V, H>
+    where K: Eq + Hash,
+          H: BuildHasher
+{
+    type Item = &'a V;
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next().map(|(_, v)| v)
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+
+    fn fold<B, F>(self, init: B, mut f: F) -> B
+        where F: FnMut(B, Self::Item) -> B
+    {
+        self.inner.fold(init, |acc, (_, v)| f(acc, v))
+    }
+}
+
+impl<'a, K, V, H> ExactSizeIterator for Values<'a, K, V, H>
+    where K: Eq + Hash,
+          H: BuildHasher
+{
+    #[inline]
+    fn len(&self) -> usize {
+        self.inner.len()
+    }
+}
+
+pub enum Entry<'m, K: 'm, V: 'm, H: 'm> {
+    Occupied(OccupiedEntry<'m, K, V, H>),
+    Vacant(VacantEntry<'m, K, V, H>),
+}
+
+impl<'m, K, V, H> Entry<'m, K, V, H>
+    where K: Eq + Hash,
+          H: BuildHasher
+{
+    pub fn key(&self) -> &K {
+        match *self {
+            Entry::Occupied(ref e) => e.key(),
+            Entry::Vacant(ref e) => e.key(),
+        }
+    }
+
+    /// Returns `true` if the key is visible in a parent tier, so that the
+    /// local binding shadows, or would shadow, an inherited one.
+    pub fn inherited(&self) -> bool {
+        match *self {
+            Entry::Occupied(ref e) => e.inherited(),
+            Entry::Vacant(ref e) => e.inherited(),
+        }
+    }
+
+    pub fn or_insert(self, default: V) -> &'m mut V {
+        match self {
+            Entry::Occupied(e) => e.into_mut(),
+            Entry::Vacant(e) => e.insert(default),
+        }
+    }
+
+    pub fn or_insert_with<F: FnOnce() -> V>(self, default: F) -> &'m mut V {
+        match self {
+            Entry::Occupied(e) => e.into_mut(),
+            Entry::Vacant(e) => e.insert(default()),
+        }
+    }
+
+    pub fn and_modify<F: FnOnce(&mut V)>(self, f: F) -> Self {
+        match self {
+            Entry::Occupied(mut e) => {
+                f(e.get_mut());
+                Entry::Occupied(e)
+            }
+            Entry::Vacant(e) => Entry::Vacant(e),
+        }
+    }
+
+    /// Like `and_modify`, but a key that is only inherited first has its
+    /// value copied into the current tier, turning it into a local entry.
+    ///
+    /// The parent tiers are left unchanged.
+    pub fn and_modify_cow<F: FnOnce(&mut V)>(self, f: F) -> Self
+        where V: Clone
+    {
+        let mut e = match self {
+            Entry::Occupied(e) => e,
+            Entry::Vacant(e) => match e.inherited_value() {
+                Some(v) => e.insert_entry(v.clone()),
+                None => return Entry::Vacant(e),
+            },
+        };
+        f(e.get_mut());
+        Entry::Occupied(e)
+    }
+}
+
+pub struct OccupiedEntry<'m, K: 'm, V: 'm, H: 'm> {
+    #[cfg(not(feature = "no_std"))]
+    inner: hash_map::OccupiedEntry<'m, K, V>,
+    #[cfg(feature = "no_std")]
+    inner: hash_map::OccupiedEntry<'m, K, V, H>,
+    parent: Option<&'m TieredMap<'m, K, V, H>>,
+}
+
+impl<'m, K, V, H> OccupiedEntry<'m, K, V, H> {
+    pub fn key(&self) -> &K {
+        self.inner.key()
+    }
+
+    /// Returns `true` if the local value shadows one visible in a parent.
+    pub fn inherited(&self) -> bool
+        where K: Eq + Hash,
+              H: BuildHasher
+    {
+        self.parent.is_some_and(|p| p.contains_key(self.inner.key()))
+    }
+
+    pub fn get(&self) -> &V {
+        self.inner.get()
+    }
+
+    pub fn get_mut(&mut self) -> &mut V {
+        self.inner.get_mut()
+    }
+
+    pub fn into_mut(self) -> &'m mut V {
+        self.inner.into_mut()
+    }
+
+    pub fn insert(&mut self, v: V) -> V {
+        self.inner.insert(v)
+    }
+}
+
+pub struct VacantEntry<'m, K: 'm, V: 'm, H: 'm> {
+    #[cfg(not(feature = "no_std"))]
+    inner: hash_map::VacantEntry<'m, K, V>,
+    #[cfg(feature = "no_std")]
+    inner: hash_map::VacantEntry<'m, K, V, H>,
+    parent: Option<&'m TieredMap<'m, K, V, H>>,
+    removed: &'m mut HashSet<K>,
+    shadowed: &'m mut usize,
+    bloom: Option<(&'m mut Bloom, u64)>,
+}
+
+impl<'m, K, V, H> VacantEntry<'m, K, V, H>
+    where K: Eq + Hash,
+          H: BuildHasher
+{
+    pub fn key(&self) -> &K {
+        self.inner.key()
+    }
+
+    /// Returns `true` if a parent tier provides a visible value for the key,
+    /// which inserting would shadow.
+    pub fn inherited(&self) -> bool {
+        self.inherited_value().is_some()
+    }
+
+    fn inherited_value(&self) -> Option<&'m V> {
+        if self.removed.contains(self.inner.key()) {
+            return None;
+        }
+        self.parent.and_then(|p| p.get(self.inner.key()))
+    }
+
+    pub fn insert(self, v: V) -> &'m mut V {
+        self.insert_entry(v).into_mut()
+    }
+
+    fn insert_entry(self, v: V) -> OccupiedEntry<'m, K, V, H> {
+        // a tombstone already counts as hiding the inherited key
+        if !self.removed.remove(self.inner.key()) &&
+           self.parent.is_some_and(|p| p.contains_key(self.inner.key())) {
+            *self.shadowed += 1;
+        }
+        if let Some((bloom, hash)) = self.bloom {
+            bloom.insert(hash);
+        }
+        OccupiedEntry {
+            inner: self.inner.insert_entry(v),
+            parent: self.parent,
+        }
+    }
+}
+
+/// Where a key's visible binding lives, as returned by `TieredMap::locate`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Location {
+    /// Defined in the current tier.
+    Local,
+    /// Inherited from the ancestor at the given depth, where the parent is 1.
+    Inherited(usize),
+    /// Not visible from the current tier.
+    Absent,
+}
+
+/// The error returned by `try_insert` when the key is already present.
+#[derive(Debug)]
+pub struct OccupiedError<K, V> {
+    pub key: K,
+    pub value: V,
+}
+
+impl<K, V> Display for OccupiedError<K, V> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.write_str("key already present")
+    }
+}
+
+impl<K: Debug, V: Debug> Error for OccupiedError<K, V> {}
+
+/// The local keys and tombstones of a tier, see `TieredMap::checkpoint`.
+#[derive(Clone, Debug)]
+pub struct Checkpoint<K> {
+    // the tier the checkpoint belongs to
+    id: ScopeId,
+    keys: HashSet<K>,
+    removed: HashSet<K>,
+}
+
+/// The error returned by `try_new_scope` when the chain would grow past the
+/// cap set with `with_max_depth`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DepthLimitExceeded;
+
+impl Display for DepthLimitExceeded {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.write_str("scope depth limit exceeded")
+    }
+}
+
+impl Error for DepthLimitExceeded {}
+
+/// The error returned by `get_checked` when no tier defines the key.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct KeyNotFound<K> {
+    pub key: K,
+}
+
+impl<K: Debug> Display for KeyNotFound<K> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "key not found: {:?}", self.key)
+    }
+}
+
+impl<K: Debug> Error for KeyNotFound<K> {}
+
+/// A child scope that merges into, or is discarded from, its parent on drop,
+/// see `TieredMap::scope_guard`.
+pub struct ScopeGuard<'p, 'a: 'p, K: 'a, V: 'a, H: 'a>
+    where K: Eq + Hash,
+          H: BuildHasher + Clone
+{
+    parent: &'p mut TieredMap<'a, K, V, H>,
+    local: HashMap<K, V, H>,
+    // inherited keys removed in the scope
+    removed: HashSet<K>,
+    merge: bool,
+}
+
+impl<'p, 'a, K, V, H> ScopeGuard<'p, 'a, K, V, H>
+    where K: Eq + Hash,
+          H: BuildHasher + Clone
+{
+    pub fn get<Q>(&self, k: &Q) -> Option<&V>
+        where K: Borrow<Q>,
+              Q: ?Sized + Hash + Eq
+    {
+        match self.local.get(k) {
+            None if self.removed.contains(k) => None,
+            None => self.parent.get(k),
+            v => v,
+        }
+    }
+
+    pub fn contains_key<Q>(&self, k: &Q) -> bool
+        where K: Borrow<Q>,
+              Q: ?Sized + Hash + Eq
+    {
+        self.get(k).is_some()
+    }
+
+    pub fn insert(&mut self, k: K, v: V) -> Option<V> {
+        self.removed.remove(&k);
+        self.local.insert(k, v)
+    }
+
+    /// Chooses whether the bindings are merged into the parent on drop.
+    pub fn set_merge(&mut self, merge: bool) {
+        self.merge = merge;
+    }
+
+    /// Runs `f` on the guarded scope as a regular child `TieredMap`.
+    ///
+    /// Inherited bindings removed by `f` stay hidden for the rest of the
+    /// guard's life.
+    pub fn scope<F, R>(&mut self, f: F) -> R
+        where F: FnOnce(&mut TieredMap<'_, K, V, H>) -> R
+    {
+        let hasher = self.local.hasher().clone();
+        let local = mem::replace(&mut self.local, HashMap::with_hasher(hasher));
+        let mut child = TieredMap::from(local);
+        child.removed = mem::take(&mut self.removed);
+        let mut child = child.with_parent(&*self.parent);
+        let r = f(&mut child);
+        self.removed = mem::take(&mut child.removed);
+        self.local = child.into_local();
+        r
+    }
+}
+
+impl<'p, 'a, K, V, H> Drop for ScopeGuard<'p, 'a, K, V, H>
+    where K: Eq + Hash,
+          H: BuildHasher + Clone
+{
+    fn drop(&mut self) {
+        if self.merge {
+            for k in self.removed.drain() {
+                self.parent.remove_owned(k);
+            }
+            self.parent.extend(self.local.drain());
+        }
+    }
+}
+
+impl<'a, K, V, H> IntoIterator for &'a TieredMap<'a, K, V, H>
+    where K: Eq + Hash,
+          H: BuildHasher
+{
+    type Item = (&'a K, &'a V);
+    type IntoIter = Iter<'a, K, V, H>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+/// Root tiers hand out their entries directly, child tiers have to clone
+/// the entries they inherit.
+impl<'a, K, V, H> IntoIterator for TieredMap<'a, K, V, H>
+    where K: Eq + Hash + Clone,
+          V: Clone,
+          H: BuildHasher
+{
+    type Item = (K, V);
+    type IntoIter = hash_map::IntoIter<K, V>;
+
+    fn into_iter(mut self) -> Self::IntoIter {
+        if let Some(p) = self.parent {
+            for (k, v) in p.iter() {
+                if !self.map.contains_key(k) && !self.removed.contains(k) {
+                    self.map.insert(k.clone(), v.clone());
+                }
+            }
+        }
+        self.map.into_iter()
+    }
+}
+
+/// Copies the current tier only; the clone links to the same parent tiers,
+/// but gets an `id` of its own.
+impl<'a, K, V, H> Clone for TieredMap<'a, K, V, H>
+    where K: Eq + Hash + Clone,
+          V: Clone,
+          H: BuildHasher + Clone
+{
+    fn clone(&self) -> Self {
+        TieredMap {
+            parent: self.parent,
+            map: self.map.clone(),
+            parent_cap: self.parent_cap,
+            parent_size: self.parent_size,
+            shadowed: self.shadowed,
+            removed: self.removed.clone(),
+            bloom: self.bloom.clone(),
+            id: ScopeId::next(),
+            scope_limit: self.scope_limit,
+        }
+    }
+}
+
+impl<'a, K, V, H> Debug for TieredMap<'a, K, V, H>
+    where K: Eq + Hash + Debug,
+          V: Debug,
+          H: BuildHasher
+{
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+/// Tier-by-tier `Debug` view, see `TieredMap::debug_tiers`.
+pub struct DebugTiers<'m, K: 'm, V: 'm, H: 'm> {
+    map: &'m TieredMap<'m, K, V, H>,
+}
+
+struct DebugTier<'m, K: 'm, V: 'm, H: 'm> {
+    depth: usize,
+    map: &'m HashMap<K, V, H>,
+}
+
+impl<'m, K, V, H> Debug for DebugTier<'m, K, V, H>
+    where K: Debug,
+          V: Debug
+{
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "tier {}: ", self.depth)?;
+        f.debug_map().entries(self.map.iter()).finish()
+    }
+}
+
+impl<'m, K, V, H> Debug for DebugTiers<'m, K, V, H>
+    where K: Debug,
+          V: Debug
+{
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let mut list = f.debug_list();
+        let mut tier = Some(self.map);
+        let mut depth = 0;
+        while let Some(t) = tier {
+            list.entry(&DebugTier { depth, map: &t.map });
+            depth += 1;
+            tier = t.parent;
+        }
+        list.finish()
+    }
+}
+
+/// Compares the visible entries, regardless of how either map is tiered.
+impl<'a, 'b, K, V, H> PartialEq<TieredMap<'b, K, V, H>> for TieredMap<'a, K, V, H>
+    where K: Eq + Hash,
+          V: PartialEq,
+          H: BuildHasher
+{
+    fn eq(&self, other: &TieredMap<'b, K, V, H>) -> bool {
+        // with equal lengths, checking one direction is enough
+        if self.len() != other.len() {
+            return false;
+        }
+        for (k, v) in self.iter() {
+            if other.get(k) != Some(v) {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+impl<'a, K, V, H, S> PartialEq<HashMap<K, V, S>> for TieredMap<'a, K, V, H>
+    where K: Eq + Hash,
+          V: PartialEq,
+          H: BuildHasher,
+          S: BuildHasher
+{
+    fn eq(&self, other: &HashMap<K, V, S>) -> bool {
+        self.len() == other.len() && self.iter().all(|(k, v)| other.get(k) == Some(v))
+    }
+}
+
+impl<'a, K, V, H, S> PartialEq<TieredMap<'a, K, V, H>> for HashMap<K, V, S>
+    where K: Eq + Hash,
+          V: PartialEq,
+          H: BuildHasher,
+          S: BuildHasher
+{
+    fn eq(&self, other: &TieredMap<'a, K, V, H>) -> bool {
+        other == self
+    }
+}
+
+impl<'a, K, V, H> Hash for TieredMap<'a, K, V, H>
+    where K: Eq + Hash,
+          V: Hash,
+          H: BuildHasher
+{
+    fn hash<S: Hasher>(&self, state: &mut S) {
+        // combine entry hashes independently of iteration order and tiering
+        let mut entries = 0;
+        for kv in self.iter() {
+            let mut h = collections::fixed_hasher();
+            kv.hash(&mut h);
+            entries ^= h.finish();
+        }
+        state.write_usize(self.len());
+        state.write_u64(entries);
+    }
+}
+
+impl<'a, K, V, H> Default for TieredMap<'a, K, V, H>
+    where K: Eq + Hash,
+          H: BuildHasher + Default
+{
+    fn default() -> Self {
+        Self::with_hasher(Default::default())
+    }
+}
+
+impl<'a, K, V, H, Q> Index<&'a Q> for TieredMap<'a, K, V, H>
+    where K: Eq + Hash + Borrow<Q>,
+          H: BuildHasher,
+          Q: Eq + Hash
+{
+    type Output = V;
+
+    /// Use `get_or_panic` to have the missing key printed.
+    #[inline]
+    fn index(&self, index: &Q) -> &Self::Output {
+        self.get(index).expect("no entry found for key")
+    }
+}
+
+
+impl<'a, K, V, H> Eq for TieredMap<'a, K, V, H>
+    where K: Eq + Hash,
+          V: Eq,
+          H: BuildHasher
+{
+}
+
+/// Always builds a single tier; later duplicates overwrite earlier ones.
+impl<'a, K, V, H> FromIterator<(K, V)> for TieredMap<'a, K, V, H>
+    where K: Eq + Hash,
+          H: BuildHasher + Default
+{
+    fn from_iter<T>(iter: T) -> Self
+        where T: IntoIterator<Item = (K, V)>
+    {
+        tm!(None, HashMap::from_iter(iter), 0, 0)
+    }
+}
+
+impl<'a, K, V, H> From<HashMap<K, V, H>> for TieredMap<'a, K, V, H>
+    where K: Eq + Hash,
+          H: BuildHasher
+{
+    fn from(map: HashMap<K, V, H>) -> Self {
+        tm!(None, map, 0, 0)
+    }
+}
+
+impl<'a, K, V, H> Extend<(K, V)> for TieredMap<'a, K, V, H>
+    where K: Eq + Hash,
+          H: BuildHasher
+{
+    fn extend<T>(&mut self, iter: T)
+        where T: IntoIterator<Item = (K, V)>
+    {
+        for (k, v) in iter {
+            self.insert(k, v);
+        }
+    }
+}
+
+impl<'a, K, V, H> Extend<(&'a K, &'a V)> for TieredMap<'a, K, V, H>
+    where K: Eq + Hash + Copy,
+          V: Copy,
+          H: BuildHasher
+{
+    fn extend<T>(&mut self, iter: T)
+        where T: IntoIterator<Item = (&'a K, &'a V)>
+    {
+        self.extend(iter.into_iter().map(|(&k, &v)| (k, v)));
+    }
+}
+
+/// Serializes the visible entries as a single flat map.
+#[cfg(feature = "serde")]
+impl<'a, K, V, H> serde::Serialize for TieredMap<'a, K, V, H>
+    where K: Eq + Hash + serde::Serialize,
+          V: serde::Serialize,
+          H: BuildHasher
+{
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+        where S: serde::Serializer
+    {
+        use serde::ser::SerializeMap;
+
+        let mut map = serializer.serialize_map(Some(self.len()))?;
+        for (k, v) in self.iter() {
+            map.serialize_entry(k, v)?;
+        }
+        map.end()
+    }
+}
+
+/// Always builds a single tier, with no parent.
+#[cfg(feature = "serde")]
+impl<'de, 'a, K, V, H> serde::Deserialize<'de> for TieredMap<'a, K, V, H>
+    where K: Eq + Hash + serde::Deserialize<'de>,
+          V: serde::Deserialize<'de>,
+          H: BuildHasher + Default
+{
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+        where D: serde::Deserializer<'de>
+    {
+        deserializer.deserialize_map(MapVisitor(PhantomData)).map(TieredMap::from)
+    }
+}
+
+// builds the map itself, as serde only has a `HashMap` impl with std
+#[cfg(feature = "serde")]
+struct MapVisitor<K, V, H>(PhantomData<(K, V, H)>);
+
+#[cfg(feature = "serde")]
+impl<'de, K, V, H> serde::de::Visitor<'de> for MapVisitor<K, V, H>
+    where K: Eq + Hash + serde::Deserialize<'de>,
+          V: serde::Deserialize<'de>,
+          H: BuildHasher + Default
+{
+    type Value = HashMap<K, V, H>;
+
+    fn expecting(&self, f: &mut Formatter) -> fmt::Result {
+        f.write_str("a map")
+    }
+
+    fn visit_map<A>(self, mut access: A) -> Result<Self::Value, A::Error>
+        where A: serde::de::MapAccess<'de>
+    {
+        let capacity = access.size_hint().unwrap_or(0);
+        let mut map = HashMap::with_capacity_and_hasher(capacity, H::default());
+        while let Some((k, v)) = access.next_entry()? {
+            map.insert(k, v);
+        }
+        Ok(map)
+    }
+}
+
+/// Collects the visible entries up front, so that they can be split evenly
+/// however the map is tiered.
+#[cfg(feature = "rayon")]
+impl<'a, K, V, H> rayon::iter::IntoParallelIterator for &'a TieredMap<'a, K, V, H>
+    where K: Eq + Hash + Sync,
+          V: Sync,
+          H: BuildHasher
+{
+    type Item = (&'a K, &'a V);
+    type Iter = ParIter<'a, K, V>;
+
+    fn into_par_iter(self) -> Self::Iter {
+        ParIter { entries: self.to_vec().into_par_iter() }
+    }
+}
+
+/// A parallel iterator over the visible entries, see `par_iter`.
+#[cfg(feature = "rayon")]
+pub struct ParIter<'a, K: 'a, V: 'a> {
+    entries: rayon::vec::IntoIter<(&'a K, &'a V)>,
+}
+
+#[cfg(feature = "rayon")]
+impl<'a, K, V> rayon::iter::ParallelIterator for ParIter<'a, K, V>
+    where K: Sync,
+          V: Sync
+{
+    type Item = (&'a K, &'a V);
+
+    fn drive_unindexed<C>(self, consumer: C) -> C::Result
+        where C: rayon::iter::plumbing::UnindexedConsumer<Self::Item>
+    {
+        self.entries.drive_unindexed(consumer)
+    }
+
+    fn opt_len(&self) -> Option<usize> {
+        self.entries.opt_len()
+    }
+}
+
+// TODO: quickcheck?
+#[cfg(test)]
+mod tests {
+    use std::borrow::Cow;
+    use std::collections::{HashSet, HashMap};
+    use std::collections::hash_map::{DefaultHasher, RandomState};
+    use std::hash::{BuildHasher, Hasher};
+    use std::iter::FromIterator;
+
+    use super::{TieredMap, Location, KeyNotFound, DepthLimitExceeded};
+
+    #[derive(Clone, Debug, PartialEq)]
+    struct Seeded(u64);
+
+    impl BuildHasher for Seeded {
+        type Hasher = DefaultHasher;
+
+        fn build_hasher(&self) -> DefaultHasher {
+            let mut h = DefaultHasher::new();
+            h.write_u64(self.0);
+            h
+        }
+    }
+
+    #[test]
+    fn scopes() {
+        let mut tm1 = TieredMap::new();
+        {
+            let mut tm2 = tm1.new_scope();
+
+            tm2.insert("a", 0);
+
+            assert_eq!(tm2.get("a"), Some(&0));
+            assert_eq!(tm1.get("a"), None);
+        }
+
+        tm1.insert("a", 1);
+
+        let mut tm2 = tm1.new_scope();
+
+        tm2.insert("b", 2);
+
+        assert_eq!(tm2.get("a"), Some(&1));
+
+        let mut tm3 = tm2.new_scope();
+
+        tm3.insert("a", 3);
+
+        assert_eq!(tm2.get("a"), Some(&1));
+        assert_eq!(tm3.get("a"), Some(&3));
+    }
+
+    #[test]
+    fn iter() {
+        let mut tm = TieredMap::new();
+        let mut hm = HashMap::new();
+
+        let entries = &[("a", 0u8), ("d", 3), ("c", 2), ("b", 1)];
+        let entries2 = &[("x", 23u8), ("y", 24), ("z", 25)];
+
+        for &(k, v) in entries {
+            tm.insert(k, v);
+            hm.insert(k, v);
+        }
+
+        let mut tm2 = tm.new_scope();
+
+        for &(k, v) in entries2 {
+            tm2.insert(k, v);
+            hm.insert(k, v);
+        }
+
+        assert_eq!(hm.iter().collect::<HashSet<_>>(),
+                   tm2.iter().collect::<HashSet<_>>());
+
+        let mut iter1 = hm.iter();
+        let mut iter2 = tm2.iter();
+
+        let (mut a, mut b);
+
+        loop {
+            assert_eq!(iter1.size_hint(), iter2.size_hint());
+
+            a = iter1.next();
+            b = iter2.next();
+
+            if a.is_none() || b.is_none() {
+                break;
+            }
+        }
+    }
+
+    #[test]
+    fn from_iter() {
+        let entries = vec![("a", 0u8), ("d", 3), ("c", 2), ("b", 1), ("z", 4)];
+        let len = entries.len();
+
+        // from_iter can't infer the hasher, see from_pairs
+        let tm = TieredMap::<_, _, RandomState>::from_iter(entries);
+
+        assert_eq!(len, tm.len());
+    }
+
+    #[test]
+    fn get_mut() {
+        let mut tm1 = TieredMap::new();
+        tm1.insert("a", 1);
+
+        let mut tm2 = tm1.new_scope();
+        tm2.insert("b", 2);
+
+        *tm2.get_mut("b").unwrap() += 10;
+
+        assert_eq!(tm2.get("b"), Some(&12));
+        assert_eq!(tm2.get_mut("a"), None);
+        assert_eq!(tm2.get("a"), Some(&1));
+    }
+
+    #[test]
+    fn is_empty() {
+        let tm1 = TieredMap::<&str, u8>::new();
+        assert!(tm1.is_empty());
+
+        let tm2 = tm1.new_scope();
+        let tm3 = tm2.new_scope();
+        let tm4 = tm3.new_scope();
+
+        assert!(tm2.is_empty());
+        assert!(tm3.is_empty());
+        assert!(tm4.is_empty());
+
+        let mut tm5 = tm4.new_scope();
+        tm5.insert("a", 0);
+
+        assert!(!tm5.is_empty());
+        assert_eq!(tm5.new_scope().new_scope().len(), 1);
+    }
+
+    #[test]
+    fn len_shadowed() {
+        let mut tm1 = TieredMap::new();
+        tm1.insert("a", 1);
+        tm1.insert("b", 1);
+
+        let mut tm2 = tm1.new_scope();
+        tm2.insert("a", 2);
+        tm2.insert("c", 2);
+
+        assert_eq!(tm2.len(), 3);
+
+        let mut tm3 = tm2.new_scope();
+        tm3.insert("a", 3);
+        tm3.insert("b", 3);
+        tm3.insert("c", 3);
+        tm3.insert("b", 4);
+
+        assert_eq!(tm3.len(), 3);
+
+        let mut tm4 = tm3.new_scope();
+        tm4.insert("d", 4);
+
+        assert_eq!(tm4.len(), 4);
+        assert_eq!(tm4.clone().len(), 4);
+        assert_eq!(tm1.len(), 2);
+    }
+
+    #[test]
+    fn iter_dedup() {
+        let mut tm1 = TieredMap::new();
+        tm1.insert("a", 1);
+        tm1.insert("b", 1);
+
+        let mut tm2 = tm1.new_scope();
+        tm2.insert("a", 2);
+
+        let mut tm3 = tm2.new_scope();
+        tm3.insert("b", 3);
+        tm3.insert("c", 3);
+
+        assert_eq!(tm3.iter().count(), tm3.len());
+        assert_eq!(tm3.iter().len(), tm3.len());
+        assert_eq!(tm3.iter().collect::<HashMap<_, _>>(),
+                   HashMap::from_iter(vec![(&"a", &2), (&"b", &3), (&"c", &3)]));
+    }
+
+    #[test]
+    fn remove() {
+        let mut tm1 = TieredMap::new();
+        tm1.insert("a", 1);
+        tm1.insert("b", 1);
+
+        let mut tm2 = tm1.new_scope();
+        tm2.insert("a", 2);
+        tm2.insert("c", 2);
+
+        // local
+        assert_eq!(tm2.remove("c"), Some(2));
+        assert_eq!(tm2.get("c"), None);
+        assert_eq!(tm2.len(), 2);
+
+        // local, uncovering the inherited value
+        assert_eq!(tm2.remove("a"), Some(2));
+        assert_eq!(tm2.get("a"), Some(&1));
+        assert_eq!(tm2.len(), 2);
+
+        // inherited
+        assert_eq!(tm2.remove("b"), None);
+        assert_eq!(tm2.remove("b"), None);
+        assert_eq!(tm2.get("b"), None);
+        assert!(!tm2.contains_key("b"));
+        assert_eq!(tm2.len(), 1);
+        assert_eq!(tm2.iter().collect::<Vec<_>>(), vec![(&"a", &1)]);
+
+        {
+            let tm3 = tm2.new_scope();
+            assert_eq!(tm3.get("b"), None);
+            assert_eq!(tm3.len(), 1);
+        }
+
+        // re-insert after tombstone
+        tm2.insert("b", 3);
+        assert_eq!(tm2.get("b"), Some(&3));
+        assert_eq!(tm2.len(), 2);
+
+        assert_eq!(tm1.get("b"), Some(&1));
+        assert_eq!(tm1.len(), 2);
+    }
+
+    #[test]
+    fn keys() {
+        let mut tm1 = TieredMap::new();
+        let mut hm = HashMap::new();
+
+        for &(k, v) in &[("a", 0u8), ("b", 1), ("c", 2)] {
+            tm1.insert(k, v);
+            hm.insert(k, v);
+        }
+
+        let mut tm2 = tm1.new_scope();
+
+        for &(k, v) in &[("c", 3u8), ("d", 4)] {
+            tm2.insert(k, v);
+            hm.insert(k, v);
+        }
+
+        assert_eq!(tm2.keys().len(), hm.len());
+        assert_eq!(tm2.keys().collect::<HashSet<_>>(),
+                   hm.keys().collect::<HashSet<_>>());
+    }
+
+    #[test]
+    fn values() {
+        let mut tm1 = TieredMap::new();
+        let mut hm = HashMap::new();
+
+        for &(k, v) in &[("a", 0u8), ("b", 1), ("c", 1)] {
+            tm1.insert(k, v);
+            hm.insert(k, v);
+        }
+
+        let mut tm2 = tm1.new_scope();
+
+        for &(k, v) in &[("a", 2u8), ("d", 1)] {
+            tm2.insert(k, v);
+            hm.insert(k, v);
+        }
+
+        let mut values = tm2.values().cloned().collect::<Vec<_>>();
+        let mut expected = hm.values().cloned().collect::<Vec<_>>();
+        values.sort();
+        expected.sort();
+
+        assert_eq!(tm2.values().len(), hm.len());
+        assert_eq!(values, expected);
+    }
+
+    #[test]
+    fn get_local() {
+        let mut tm1 = TieredMap::new();
+        tm1.insert("a", 1);
+
+        let mut tm2 = tm1.new_scope();
+        tm2.insert("b", 2);
+
+        assert_eq!(tm2.get_local("a"), None);
+        assert_eq!(tm2.get("a"), Some(&1));
+        assert_eq!(tm2.get_local("b"), Some(&2));
+        assert!(!tm2.contains_key_local("a"));
+        assert!(tm2.contains_key_local("b"));
+        assert!(tm1.contains_key_local("a"));
+    }
+
+    #[test]
+    fn depth() {
+        let mut tm1 = TieredMap::new();
+        assert_eq!(tm1.depth(), 1);
+        tm1.insert("a", 1);
+
+        let tm2 = tm1.new_scope();
+        assert_eq!(tm2.depth(), 2);
+
+        // tm2 is empty, so it is skipped
+        let mut tm3 = tm2.new_scope();
+        assert_eq!(tm3.depth(), 2);
+        tm3.insert("b", 2);
+
+        let mut tm4 = tm3.new_scope();
+        assert_eq!(tm4.depth(), 3);
+        tm4.insert("c", 3);
+
+        let tm5 = tm4.new_scope();
+        let tm6 = tm5.new_scope();
+        assert_eq!(tm6.depth(), 4);
+    }
+
+    #[test]
+    fn tier_len() {
+        let mut tm1 = TieredMap::new();
+        tm1.insert("a", 1);
+        tm1.insert("b", 1);
+
+        let mut tm2 = tm1.new_scope();
+        assert!(tm2.tier_is_empty());
+        tm2.insert("c", 2);
+
+        assert_eq!(tm2.tier_len(), 1);
+        assert!(!tm2.tier_is_empty());
+        assert_eq!(tm2.len(), 3);
+    }
+
+    #[test]
+    fn iter_local() {
+        let mut tm1 = TieredMap::new();
+        tm1.insert("a", 1);
+        tm1.insert("b", 1);
+
+        let mut tm2 = tm1.new_scope();
+        tm2.insert("b", 2);
+        tm2.insert("c", 2);
+
+        assert_eq!(tm2.iter_local().collect::<HashSet<_>>(),
+                   HashSet::from_iter(vec![(&"b", &2), (&"c", &2)]));
+    }
+
+    #[test]
+    fn hashmap_conversions() {
+        let mut hm = HashMap::with_hasher(Seeded(7));
+        hm.insert("a", 1);
+        hm.insert("b", 1);
+
+        let tm1 = TieredMap::from(hm.clone());
+
+        assert_eq!(tm1.hasher(), &Seeded(7));
+        assert_eq!(tm1.len(), 2);
+        assert_eq!(tm1.get("a"), Some(&1));
+
+        let mut tm2 = tm1.new_scope();
+        tm2.insert("b", 2);
+        tm2.insert("c", 2);
+        hm.insert("b", 2);
+        hm.insert("c", 2);
+
+        let flat = tm2.to_hashmap();
+
+        assert_eq!(flat.hasher(), &Seeded(7));
+        assert_eq!(flat, hm);
+    }
+
+    #[test]
+    fn entry() {
+        let mut tm1 = TieredMap::new();
+        tm1.insert("a", 1);
+
+        let mut tm2 = tm1.new_scope();
+
+        *tm2.entry("b").or_insert(2) += 10;
+        *tm2.entry("b").or_insert(2) += 10;
+        assert_eq!(tm2.get("b"), Some(&22));
+
+        // inherited keys are vacant locally
+        assert_eq!(*tm2.entry("a").and_modify(|v| *v += 1).or_insert_with(|| 5), 5);
+        assert_eq!(tm2.get("a"), Some(&5));
+        assert_eq!(tm1.get("a"), Some(&1));
+        assert_eq!(tm2.len(), 2);
+
+        tm2.entry("a").and_modify(|v| *v += 1);
+        assert_eq!(tm2.get("a"), Some(&6));
+        assert_eq!(tm2.entry("c").key(), &"c");
+    }
+
+    #[test]
+    fn into_iter() {
+        let mut tm1 = TieredMap::new();
+        tm1.insert("a".to_string(), 1);
+        tm1.insert("b".to_string(), 1);
+
+        let mut tm2 = tm1.new_scope();
+        tm2.insert("b".to_string(), 2);
+        tm2.insert("c".to_string(), 2);
+
+        assert_eq!(tm2.into_iter().collect::<HashMap<_, _>>(),
+                   HashMap::from_iter(vec![("a".to_string(), 1),
+                                           ("b".to_string(), 2),
+                                           ("c".to_string(), 2)]));
+
+        let owned = tm1.into_iter().collect::<HashMap<_, _>>();
+
+        assert_eq!(owned.len(), 2);
+        assert_eq!(owned["b"], 1);
+    }
+
+    #[test]
+    fn values_mut() {
+        let mut tm1 = TieredMap::new();
+        tm1.insert("a", 1);
+        tm1.insert("b", 1);
+
+        let mut tm2 = tm1.new_scope();
+        tm2.insert("b", 2);
+        tm2.insert("c", 3);
+
+        for v in tm2.values_mut() {
+            *v *= 10;
+        }
+
+        assert_eq!(tm2.get("a"), Some(&1));
+        assert_eq!(tm2.get("b"), Some(&20));
+        assert_eq!(tm2.get("c"), Some(&30));
+        assert_eq!(tm1.get("b"), Some(&1));
+    }
+
+    #[test]
+    fn retain() {
+        let mut tm1 = TieredMap::new();
+        tm1.insert(1, 1);
+        tm1.insert(2, 1);
+
+        let mut tm2 = tm1.new_scope();
+        for i in 1..7 {
+            tm2.insert(i, i * 10 + i);
+        }
+        assert_eq!(tm2.len(), 6);
+
+        tm2.retain(|_, v| *v % 2 == 0);
+
+        assert_eq!(tm2.iter_local().collect::<HashSet<_>>(),
+                   HashSet::from_iter(vec![(&2, &22), (&4, &44), (&6, &66)]));
+        assert_eq!(tm2.get(&1), Some(&1));
+        assert_eq!(tm2.len(), 4);
+    }
+
+    #[test]
+    fn clear() {
+        let mut tm1 = TieredMap::new();
+        tm1.insert("a", 1);
+        tm1.insert("b", 1);
+
+        let mut tm2 = tm1.new_scope();
+        tm2.insert("a", 2);
+        tm2.insert("c", 2);
+        tm2.remove("b");
+
+        tm2.clear();
+
+        assert!(tm2.tier_is_empty());
+        assert_eq!(tm2.get("a"), Some(&1));
+        assert_eq!(tm2.get("b"), Some(&1));
+        assert_eq!(tm2.get("c"), None);
+        assert_eq!(tm2.len(), 2);
+    }
+
+    #[test]
+    fn flatten() {
+        let mut tm1 = TieredMap::new();
+        tm1.insert("a", 1);
+        tm1.insert("b", 1);
+
+        let mut tm2 = tm1.new_scope();
+        tm2.insert("b", 2);
+
+        let mut tm3 = tm2.new_scope();
+        tm3.insert("c", 3);
+        tm3.remove("a");
+
+        let flat = tm3.flatten();
+
+        assert_eq!(flat.depth(), 1);
+        assert_eq!(flat.get("b"), Some(&2));
+        assert_eq!(flat, tm3);
+    }
+
+    #[test]
+    fn get_key_value() {
+        #[derive(Debug)]
+        struct Tagged(&'static str, u8);
+
+        impl PartialEq for Tagged {
+            fn eq(&self, other: &Tagged) -> bool {
+                self.0 == other.0
+            }
+        }
+
+        impl Eq for Tagged {}
+
+        impl ::std::hash::Hash for Tagged {
+            fn hash<S: Hasher>(&self, state: &mut S) {
+                self.0.hash(state);
+            }
+        }
+
+        let mut tm1 = TieredMap::new();
+        tm1.insert(Tagged("a", 1), 1);
+        tm1.insert(Tagged("b", 1), 1);
+
+        let mut tm2 = tm1.new_scope();
+        tm2.insert(Tagged("b", 2), 2);
+
+        let (k, v) = tm2.get_key_value(&Tagged("a", 0)).unwrap();
+        assert_eq!((k.1, *v), (1, 1));
+
+        let (k, v) = tm2.get_key_value(&Tagged("b", 0)).unwrap();
+        assert_eq!((k.1, *v), (2, 2));
+
+        assert!(tm2.get_key_value(&Tagged("c", 0)).is_none());
+    }
+
+    #[test]
+    fn deep_chain() {
+        let mut root = TieredMap::new();
+        root.insert("root", 0);
+        root.insert("x", 0);
+
+        // leak the tiers so the chain can be built in a loop
+        let mut tier: &'static TieredMap<_, _> = Box::leak(Box::new(root));
+        for i in 1..10_000 {
+            let mut child = tier.new_scope();
+            child.insert("x", i);
+            tier = Box::leak(Box::new(child));
+        }
+
+        assert_eq!(tier.depth(), 10_000);
+        assert_eq!(tier.get("root"), Some(&0));
+        assert_eq!(tier.get("x"), Some(&9_999));
+        assert!(!tier.contains_key("y"));
+        assert_eq!(tier.len(), 2);
+    }
+
+    #[test]
+    fn bloom() {
+        let mut tm1 = TieredMap::with_bloom();
+        for i in 0..100 {
+            tm1.insert(i, i);
+        }
+
+        let mut tm2 = tm1.new_scope();
+        for i in 100..200 {
+            tm2.insert(i, i);
+        }
+        tm2.entry(200).or_insert(200);
+
+        let mut tm3 = tm2.new_scope();
+        tm3.insert(0, 1000);
+
+        for i in 1..201 {
+            assert_eq!(tm3.get(&i), Some(&i));
+        }
+        assert_eq!(tm3.get(&0), Some(&1000));
+        for i in 201..1000 {
+            assert_eq!(tm3.get(&i), None);
+            assert!(!tm3.contains_key(&i));
+        }
+        assert_eq!(tm3.len(), 201);
+
+        let tm4 = tm3.clone();
+        assert_eq!(tm4.get(&150), Some(&150));
+        assert_eq!(tm4.get(&1500), None);
+    }
+
+    #[test]
+    fn hash() {
+        let mut flat = TieredMap::new();
+        flat.insert("a", 1);
+        flat.insert("b", 2);
+
+        let mut tm1 = TieredMap::new();
+        tm1.insert("a", 1);
+        tm1.insert("b", 0);
+        tm1.insert("c", 0);
+
+        let mut tm2 = tm1.new_scope();
+        tm2.insert("b", 2);
+        tm2.remove("c");
+
+        let mut flat2 = flat.clone();
+        flat2.insert("b", 3);
+
+        let set = HashSet::<_>::from_iter(vec![&flat, &tm2, &flat2]);
+
+        assert_eq!(set.len(), 2);
+        assert!(set.contains(&tm2));
+    }
+
+    #[test]
+    fn into_local() {
+        let mut tm1 = TieredMap::new();
+        tm1.insert("a", 1);
+        tm1.insert("b", 1);
+
+        let mut tm2 = tm1.new_scope();
+        tm2.insert("b", 2);
+        tm2.insert("c", 2);
+
+        let local = tm2.into_local();
+        assert_eq!(local.len(), 2);
+
+        tm1.extend(local);
+
+        assert_eq!(tm1.get("a"), Some(&1));
+        assert_eq!(tm1.get("b"), Some(&2));
+        assert_eq!(tm1.get("c"), Some(&2));
+        assert_eq!(tm1.len(), 3);
+    }
+
+    #[test]
+    fn from_pairs() {
+        let tm = TieredMap::from_pairs(vec![("a", 0u8), ("b", 1), ("a", 2)]);
+
+        assert_eq!(tm.len(), 2);
+        assert_eq!(tm.depth(), 1);
+        assert_eq!(tm.get("a"), Some(&2));
+    }
+
+    #[test]
+    fn try_insert() {
+        let mut tm1 = TieredMap::new();
+        tm1.insert("a", 1);
+
+        let mut tm2 = tm1.new_scope();
+
+        let err = tm2.try_insert("a", 2).unwrap_err();
+        assert_eq!((err.key, err.value), ("a", 2));
+        assert_eq!(tm2.try_insert("b", 2).unwrap(), &2);
+        assert!(tm2.try_insert("b", 3).is_err());
+
+        assert_eq!(tm2.try_insert_local("a", 3).unwrap(), &3);
+        assert_eq!(tm2.try_insert_local("a", 4).unwrap_err().value, 4);
+        assert_eq!(tm2.get("a"), Some(&3));
+        assert_eq!(tm1.get("a"), Some(&1));
+    }
+
+    #[test]
+    fn shadows() {
+        let mut tm1 = TieredMap::new();
+        tm1.insert("a", 1);
+        tm1.insert("b", 1);
+
+        let mut tm2 = tm1.new_scope();
+        tm2.insert("a", 2);
+        tm2.insert("c", 2);
+
+        let mut tm3 = tm2.new_scope();
+        tm3.insert("a", 3);
+
+        assert!(tm2.shadows("a"));
+        assert!(!tm2.shadows("b"));
+        assert!(!tm2.shadows("c"));
+        assert!(!tm1.shadows("a"));
+
+        assert_eq!(tm2.shadowed_value("a"), Some(&1));
+        assert_eq!(tm3.shadowed_value("a"), Some(&2));
+        assert_eq!(tm3.shadowed_value("c"), None);
+    }
+
+    #[test]
+    fn get_all() {
+        let mut tm1 = TieredMap::new();
+        tm1.insert("a", 1);
+
+        let mut tm2 = tm1.new_scope();
+        tm2.insert("a", 2);
+        tm2.insert("b", 2);
+
+        let mut tm3 = tm2.new_scope();
+        tm3.insert("a", 3);
+
+        assert_eq!(tm3.get_all("a"), vec![&3, &2, &1]);
+        assert_eq!(tm3.get_all("b"), vec![&2]);
+        assert!(tm3.get_all("c").is_empty());
+    }
+
+    #[test]
+    fn parent() {
+        let mut tm1 = TieredMap::new();
+        tm1.insert("a", 1);
+
+        let mut tm2 = tm1.new_scope();
+        tm2.insert("b", 2);
+
+        let mut tm3 = tm2.new_scope();
+        tm3.insert("c", 3);
+
+        let mut tiers = 1;
+        let mut tier = &tm3;
+        while let Some(p) = tier.parent() {
+            tiers += 1;
+            tier = p;
+        }
+
+        assert_eq!(tiers, tm3.depth());
+        assert_eq!(tier.get_local("a"), Some(&1));
+        assert!(tm1.parent().is_none());
+    }
+
+    #[test]
+    fn eq() {
+        let flat = TieredMap::from_pairs(vec![("a", 1), ("b", 2), ("c", 3)]);
+
+        let mut tm1 = TieredMap::new();
+        tm1.insert("a", 1);
+        tm1.insert("b", 0);
+
+        let mut tm2 = tm1.new_scope();
+        tm2.insert("b", 2);
+
+        let mut tm3 = tm2.new_scope();
+        tm3.insert("c", 3);
+
+        assert_eq!(tm3, flat);
+        assert_eq!(flat, tm3);
+
+        let mut tm4 = tm2.new_scope();
+        tm4.insert("c", 3);
+        tm4.insert("b", 0);
+
+        assert!(tm4 != tm3);
+        assert!(flat != tm4);
+        assert!(tm2 != flat);
+    }
+
+    #[test]
+    fn capacity_local() {
+        let mut tm1 = TieredMap::<u32, u32>::new();
+        tm1.insert(0, 0);
+        let parent_cap = tm1.capacity();
+
+        let mut tm2 = tm1.new_scope();
+        assert_eq!(tm2.capacity_local(), 0);
+
+        tm2.reserve(100);
+
+        assert!(tm2.capacity_local() >= 100);
+        assert_eq!(tm2.capacity(), parent_cap + tm2.capacity_local());
+        assert_eq!(tm1.capacity(), parent_cap);
+    }
+
+    #[test]
+    fn reserve_exact() {
+        let mut tm1 = TieredMap::new();
+        tm1.insert(0, 0);
+
+        let mut tm2 = tm1.new_scope();
+        tm2.insert(1, 1);
+        tm2.reserve_exact(100);
+        let cap = tm2.capacity_local();
+
+        // room for `additional` keys beyond the current ones
+        for i in 2..102 {
+            tm2.insert(i, i);
+        }
+        assert_eq!(tm2.capacity_local(), cap);
+        assert_eq!(tm2.tier_len(), 101);
+    }
+
+    #[test]
+    fn try_reserve() {
+        let mut tm = TieredMap::<u32, u32>::new();
+
+        assert!(tm.try_reserve(10).is_ok());
+        assert!(tm.capacity_local() >= 10);
+        assert!(tm.try_reserve(usize::MAX).is_err());
+    }
+
+    #[test]
+    fn drain() {
+        let mut tm1 = TieredMap::new();
+        tm1.insert("a", 1);
+        tm1.insert("b", 1);
+
+        let mut tm2 = tm1.new_scope();
+        tm2.insert("a", 2);
+        tm2.insert("c", 2);
+
+        assert_eq!(tm2.drain().collect::<HashSet<_>>(),
+                   HashSet::from_iter(vec![("a", 2), ("c", 2)]));
+
+        assert!(tm2.tier_is_empty());
+        assert_eq!(tm2.get("a"), Some(&1));
+        assert_eq!(tm2.get("c"), None);
+        assert_eq!(tm2.len(), 2);
+    }
+
+    #[test]
+    fn new_scope_with_capacity() {
+        let mut tm1 = TieredMap::<u32, u32>::new();
+        tm1.insert(0, 0);
+
+        let tm2 = tm1.new_scope();
+        let mut tm3 = tm2.new_scope_with_capacity(50);
+
+        assert!(tm3.capacity_local() >= 50);
+        assert_eq!(tm3.depth(), 2);
+
+        tm3.insert(1, 1);
+        assert_eq!(tm3.get(&0), Some(&0));
+    }
+
+    #[test]
+    fn new_scope_with_hasher() {
+        struct Unique(u64);
+
+        impl BuildHasher for Unique {
+            type Hasher = DefaultHasher;
+
+            fn build_hasher(&self) -> DefaultHasher {
+                let mut h = DefaultHasher::new();
+                h.write_u64(self.0);
+                h
+            }
+        }
+
+        let mut tm1 = TieredMap::with_hasher(Unique(1));
+        tm1.insert("a", 1);
+
+        let mut tm2 = tm1.new_scope_with_hasher(Unique(2));
+        tm2.insert("a", 2);
+        tm2.insert("b", 2);
+
+        let tm3 = tm2.new_scope_with_hasher(Unique(3));
+
+        assert_eq!(tm3.get("a"), Some(&2));
+        assert_eq!(tm3.get("b"), Some(&2));
+        assert_eq!(tm3.len(), 2);
+        assert_eq!(tm3.depth(), 3);
+    }
+
+    #[test]
+    fn extend_counting() {
+        let mut tm1 = TieredMap::new();
+        tm1.insert("a", 1);
+
+        let mut tm2 = tm1.new_scope();
+        tm2.insert("b", 2);
+
+        let new = tm2.extend_counting(vec![("a", 3), ("b", 3), ("c", 3), ("c", 4)]);
+
+        assert_eq!(new, 2);
+        assert_eq!(tm2.len(), 3);
+        assert_eq!(tm2.get("c"), Some(&4));
+    }
+
+    #[test]
+    fn index() {
+        let mut tm1 = TieredMap::new();
+        tm1.insert("a", 1);
+
+        let tm2 = tm1.new_scope();
+
+        assert_eq!(tm2[&"a"], 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "no entry found for key")]
+    fn index_missing() {
+        let mut tm1 = TieredMap::new();
+        tm1.insert("a", 1);
+
+        let tm2 = tm1.new_scope();
+
+        let _ = tm2[&"missing"];
+    }
+
+    #[test]
+    fn index_non_debug_key() {
+        #[derive(PartialEq, Eq, Hash)]
+        struct Opaque(u8);
+
+        let mut tm = TieredMap::new();
+        tm.insert(Opaque(1), 1);
+        assert_eq!(tm[&Opaque(1)], 1);
+    }
+
+    #[test]
+    fn get_or_panic() {
+        let mut tm1 = TieredMap::new();
+        tm1.insert("a", 1);
+        let tm2 = tm1.new_scope();
+        assert_eq!(*tm2.get_or_panic("a"), 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "no entry found for key \"missing\"")]
+    fn get_or_panic_missing() {
+        let mut tm1 = TieredMap::new();
+        tm1.insert("a", 1);
+
+        let tm2 = tm1.new_scope();
+
+        let _ = tm2.get_or_panic("missing");
+    }
+
+    #[test]
+    fn contains_value() {
+        let mut tm1 = TieredMap::new();
+        tm1.insert("a", 1);
+        tm1.insert("b", 2);
+
+        let mut tm2 = tm1.new_scope();
+        tm2.insert("a", 3);
+
+        assert!(tm2.contains_value(&2));
+        assert!(tm2.contains_value(&3));
+        assert!(!tm2.contains_value(&1));
+        assert!(tm1.contains_value(&1));
+    }
+
+    #[test]
+    fn find_key_by_value() {
+        let mut tm1 = TieredMap::new();
+        tm1.insert("a", 1);
+        tm1.insert("b", 2);
+        tm1.insert("c", 3);
+
+        let mut tm2 = tm1.new_scope();
+        tm2.insert("a", 4);
+        tm2.insert("d", 3);
+
+        assert_eq!(tm2.find_key_by_value(&2), Some(&"b"));
+        assert_eq!(tm2.find_key_by_value(&4), Some(&"a"));
+        assert_eq!(tm2.find_key_by_value(&3), Some(&"d"));
+        assert_eq!(tm2.find_key_by_value(&1), None);
+    }
+
+    #[test]
+    fn scope_path() {
+        let mut tm1 = TieredMap::new();
+        tm1.insert(0, 0);
+        tm1.insert(1, 0);
+        tm1.insert(2, 0);
+
+        let mut tm2 = tm1.new_scope();
+        tm2.insert(3, 0);
+
+        // skipped
+        let tm3 = tm2.new_scope();
+
+        let mut tm4 = tm3.new_scope();
+        tm4.insert(4, 0);
+        tm4.insert(5, 0);
+
+        let tm5 = tm4.new_scope();
+
+        assert_eq!(tm5.scope_path(), vec![0, 2, 1, 3]);
+        assert_eq!(tm5.scope_path().len(), tm5.depth());
+    }
+
+    #[test]
+    fn effective_len() {
+        let mut tm1 = TieredMap::new();
+        tm1.insert("a", 1);
+        tm1.insert("b", 1);
+        tm1.insert("c", 1);
+
+        let mut tm2 = tm1.new_scope();
+        tm2.insert("a", 2);
+        tm2.insert("d", 2);
+        tm2.remove("b");
+
+        let mut tm3 = tm2.new_scope();
+        tm3.insert("a", 3);
+        tm3.insert("b", 3);
+        tm3.remove("c");
+
+        for tm in &[&tm1, &tm2, &tm3] {
+            assert_eq!(tm.effective_len(), tm.to_hashmap().len());
+            assert_eq!(tm.effective_len(), tm.len());
+        }
+    }
+
+    #[test]
+    fn get_or_insert_with() {
+        let mut tm1 = TieredMap::new();
+        tm1.insert("a", 1);
+        tm1.insert("b", 1);
+
+        let mut tm2 = tm1.new_scope();
+        tm2.insert("c", 2);
+        tm2.remove("b");
+
+        assert_eq!(tm2.get_or_insert_with("a", || 10), &1);
+        assert_eq!(tm2.get_or_insert_with("c", || 10), &2);
+        assert_eq!(tm2.get_or_insert_with("d", || 10), &10);
+        assert_eq!(tm2.get_or_insert_with("b", || 10), &10);
+
+        assert_eq!(tm2.get_local("a"), None);
+        assert_eq!(tm2.get_local("d"), Some(&10));
+        assert_eq!(tm2.len(), 4);
+    }
+
+    #[test]
+    fn iter_with_tier() {
+        let mut tm1 = TieredMap::new();
+        tm1.insert("a", 1);
+        tm1.insert("b", 1);
+
+        let mut tm2 = tm1.new_scope();
+        tm2.insert("c", 2);
+
+        let mut tm3 = tm2.new_scope();
+        tm3.insert("a", 3);
+
+        assert_eq!(tm3.iter_with_tier().collect::<HashSet<_>>(),
+                   HashSet::from_iter(vec![(&"a", &3, 0), (&"b", &1, 2), (&"c", &2, 1)]));
+    }
+
+    #[test]
+    fn clone_shares_parent() {
+        let mut tm1 = TieredMap::new();
+        tm1.insert("a", 1);
+
+        let mut tm2 = tm1.new_scope();
+        tm2.insert("b", 2);
+
+        let mut tm3 = tm2.clone();
+        tm3.insert("b", 3);
+        tm3.insert("c", 3);
+
+        assert!(tm3.parent().is_some_and(|p| ::std::ptr::eq(p, &tm1)));
+        assert_eq!(tm3.get("a"), Some(&1));
+        assert_eq!(tm2.get("b"), Some(&2));
+        assert_eq!(tm2.get("c"), None);
+    }
+
+    #[test]
+    fn root() {
+        let mut tm1 = TieredMap::new();
+        tm1.insert("a", 1);
+        assert!(::std::ptr::eq(tm1.root(), &tm1));
+
+        let mut tm2 = tm1.new_scope();
+        tm2.insert("b", 2);
+
+        let mut tm3 = tm2.new_scope();
+        tm3.insert("c", 3);
+
+        let tm4 = tm3.new_scope();
+
+        assert!(::std::ptr::eq(tm4.root(), &tm1));
+        assert_eq!(tm4.root().iter_local().collect::<Vec<_>>(), vec![(&"a", &1)]);
+    }
+
+    #[test]
+    fn difference_intersection() {
+        let mut tm1 = TieredMap::new();
+        tm1.insert("a", 1);
+        tm1.insert("b", 1);
+        tm1.insert("x", 1);
+
+        let mut tm2 = tm1.new_scope();
+        tm2.insert("a", 2);
+        tm2.insert("c", 2);
+        tm2.remove("x");
+
+        let other = TieredMap::from_pairs(vec![("a", 0), ("c", 0), ("d", 0), ("x", 0)]);
+
+        assert_eq!(tm2.difference(&other).collect::<HashSet<_>>(),
+                   HashSet::from_iter(vec![&"b"]));
+        assert_eq!(tm2.intersection(&other).collect::<HashSet<_>>(),
+                   HashSet::from_iter(vec![&"a", &"c"]));
+        assert_eq!(other.difference(&tm2).collect::<HashSet<_>>(),
+                   HashSet::from_iter(vec![&"d", &"x"]));
+    }
+
+    #[test]
+    fn from_scopes() {
+        let scopes = vec![vec![("a", 1), ("b", 1), ("c", 1)],
+                          vec![("b", 2)],
+                          vec![("c", 3), ("d", 3)]];
+
+        let (depth, entries) = TieredMap::from_scopes(scopes, |tm| {
+            (tm.depth(), tm.iter().map(|(&k, &v)| (k, v)).collect::<HashMap<_, _>>())
+        });
+
+        assert_eq!(depth, 3);
+        assert_eq!(entries,
+                   HashMap::from_iter(vec![("a", 1), ("b", 2), ("c", 3), ("d", 3)]));
+    }
+
+    #[test]
+    fn shrink_to() {
+        let mut tm = TieredMap::<u32, u32>::with_capacity(1000);
+        for i in 0..5 {
+            tm.insert(i, i);
+        }
+
+        let before = tm.capacity_local();
+        tm.shrink_to(100);
+
+        assert!(tm.capacity_local() < before);
+        assert!(tm.capacity_local() >= 100);
+        assert_eq!(tm.len(), 5);
+    }
+
+    #[test]
+    fn with_parent() {
+        let mut tm1 = TieredMap::with_bloom();
+        tm1.insert("a", 1);
+        tm1.insert("b", 1);
+
+        let mut other = TieredMap::with_bloom();
+        other.insert("b", 10);
+        other.insert("c", 10);
+        other.insert("d", 10);
+
+        let mut tm2 = tm1.new_scope();
+        tm2.insert("b", 2);
+        tm2.insert("e", 2);
+        tm2.remove("a");
+        assert_eq!(tm2.len(), 2);
+
+        let tm2 = tm2.with_parent(&other);
+
+        assert_eq!(tm2.get("a"), None);
+        assert_eq!(tm2.get("b"), Some(&2));
+        assert_eq!(tm2.get("c"), Some(&10));
+        assert_eq!(tm2.get("d"), Some(&10));
+        assert_eq!(tm2.len(), 4);
+        assert_eq!(tm2.len(), tm2.effective_len());
+        assert_eq!(tm2.capacity(), other.capacity() + tm2.capacity_local());
+    }
+
+    #[test]
+    fn as_hashmap() {
+        let tm1 = TieredMap::from_pairs(vec![("a", 1)]);
+        let mut tm2 = tm1.new_scope();
+        tm2.insert("b", 2);
+
+        assert_eq!(tm1.as_hashmap(), Some(&HashMap::from_iter(vec![("a", 1)])));
+        assert_eq!(tm2.as_hashmap(), None);
+    }
+
+    #[test]
+    fn entry_cow() {
+        let mut tm1 = TieredMap::new();
+        tm1.insert("a", vec![1]);
+
+        let mut tm2 = tm1.new_scope();
+        tm2.insert("b", vec![2]);
+
+        tm2.entry_cow("a").unwrap().push(10);
+        tm2.entry_cow("b").unwrap().push(20);
+        assert!(tm2.entry_cow("c").is_none());
+        tm2.entry_cow_or("c", vec![3]).push(30);
+        tm2.entry_cow_or("a", vec![]).push(11);
+
+        assert_eq!(tm2.get_local("a"), Some(&vec![1, 10, 11]));
+        assert_eq!(tm2.get("b"), Some(&vec![2, 20]));
+        assert_eq!(tm2.get("c"), Some(&vec![3, 30]));
+        assert_eq!(tm1.get("a"), Some(&vec![1]));
+        assert_eq!(tm2.len(), 3);
+    }
+
+    #[test]
+    fn is_subset() {
+        let mut tm1 = TieredMap::new();
+        tm1.insert("a", 1);
+        tm1.insert("b", 1);
+
+        let mut tm2 = tm1.new_scope();
+        tm2.insert("b", 2);
+        tm2.insert("c", 2);
+
+        let small = TieredMap::from_pairs(vec![("a", 1), ("b", 1)]);
+        let same = TieredMap::from_pairs(vec![("a", 1), ("b", 2), ("c", 2)]);
+        let disjoint = TieredMap::from_pairs(vec![("x", 1)]);
+
+        assert!(small.is_subset(&tm2));
+        assert!(tm2.is_superset(&small));
+        assert!(!tm2.is_subset(&small));
+        assert!(!small.is_submap(&tm2)); // "b" is shadowed in tm2
+
+        assert!(same.is_subset(&tm2) && tm2.is_subset(&same));
+        assert!(same.is_submap(&tm2));
+
+        assert!(!disjoint.is_subset(&tm2));
+        assert!(!tm2.is_subset(&disjoint));
+    }
+
+    #[test]
+    fn debug_tiers() {
+        let mut tm1 = TieredMap::new();
+        tm1.insert("a", 1);
+
+        let mut tm2 = tm1.new_scope();
+        tm2.insert("a", 2);
+
+        assert_eq!(format!("{:?}", tm2), r#"{"a": 2}"#);
+        assert_eq!(format!("{:?}", tm2.debug_tiers()),
+                   r#"[tier 0: {"a": 2}, tier 1: {"a": 1}]"#);
+        assert!(format!("{:#?}", tm2.debug_tiers()).contains("tier 1: {\n"));
+    }
+
+    #[test]
+    fn tombstone_count() {
+        let mut tm1 = TieredMap::new();
+        for &k in &["a", "b", "c", "d"] {
+            tm1.insert(k, 1);
+        }
+
+        let mut tm2 = tm1.new_scope();
+        tm2.insert("d", 2);
+        assert_eq!(tm2.tombstone_count(), 0);
+
+        tm2.remove("a");
+        tm2.remove("b");
+        tm2.remove("b");
+        // a local binding is dropped without leaving a tombstone
+        tm2.remove("d");
+        assert_eq!(tm2.tombstone_count(), 2);
+
+        assert_eq!(tm2.len(), 2);
+        assert!(!tm2.contains_key("a") && !tm2.contains_key("b"));
+        let mut keys = tm2.keys().cloned().collect::<Vec<_>>();
+        keys.sort();
+        assert_eq!(keys, vec!["c", "d"]);
+        assert_eq!(tm2.values().sum::<i32>(), 2);
+        assert_eq!(tm2.iter().len(), 2);
+        assert_eq!(tm1.len(), 4);
+    }
+
+    #[test]
+    fn compact() {
+        let mut tm1 = TieredMap::new();
+        tm1.insert("a", 1);
+        tm1.insert("b", 1);
+        let mut other = TieredMap::new();
+        other.insert("b", 3);
+
+        let mut tm2 = tm1.new_scope();
+        tm2.remove("a");
+        tm2.remove("b");
+        tm2.compact();
+        assert_eq!(tm2.tombstone_count(), 2);
+
+        let mut tm2 = tm2.with_parent(&other);
+        assert_eq!(tm2.len(), 0);
+        tm2.compact();
+        assert_eq!(tm2.tombstone_count(), 1);
+        assert_eq!(tm2.len(), 0);
+        assert_eq!(tm2.get("b"), None);
+    }
+
+    #[test]
+    fn get_many_mut() {
+        let mut tm1 = TieredMap::new();
+        tm1.insert("a", 1);
+
+        let mut tm2 = tm1.new_scope();
+        tm2.insert("b", 2);
+        tm2.insert("c", 2);
+
+        if let Some([b, c]) = tm2.get_many_mut(["b", "c"]) {
+            *b += 10;
+            *c += 20;
+        } else {
+            panic!("expected both local entries");
+        }
+        assert_eq!(tm2.get("b"), Some(&12));
+        assert_eq!(tm2.get("c"), Some(&22));
+
+        assert!(tm2.get_many_mut(["b", "b"]).is_none());
+        // "a" is only inherited
+        assert!(tm2.get_many_mut(["a", "b"]).is_none());
+        assert!(tm2.get_many_mut(["b", "x"]).is_none());
+    }
+
+    #[test]
+    fn extend_if_absent() {
+        let mut tm1 = TieredMap::new();
+        tm1.insert("a", 1);
+
+        let mut tm2 = tm1.new_scope();
+        tm2.insert("b", 2);
+        tm2.extend_if_absent(vec![("a", 3), ("b", 3), ("c", 3), ("c", 4)]);
+
+        assert_eq!(tm2.get("a"), Some(&1));
+        assert_eq!(tm2.get("b"), Some(&2));
+        assert_eq!(tm2.get("c"), Some(&3));
+        assert!(!tm2.contains_key_local("a"));
+        assert_eq!(tm2.len(), 3);
+    }
+
+    #[test]
+    fn visible_key_set() {
+        let mut tm1 = TieredMap::new();
+        tm1.insert("a", 1);
+        tm1.insert("b", 1);
+
+        let mut tm2 = tm1.new_scope();
+        tm2.insert("b", 2);
+        tm2.insert("c", 2);
+
+        let expected = ["a", "b", "c"].iter().cloned().collect::<HashSet<_>>();
+        let set = tm2.visible_key_set();
+        assert_eq!(set, expected);
+        assert_eq!(set.len(), 3);
+    }
+
+    #[test]
+    fn map_values() {
+        let mut tm1 = TieredMap::new();
+        tm1.insert("a", 1u8);
+        tm1.insert("b", 1);
+
+        let mut tm2 = tm1.new_scope();
+        tm2.insert("b", 2);
+        tm2.insert("c", 3);
+
+        let mapped = tm2.map_values(|v| v.to_string());
+        assert!(mapped.parent().is_none());
+        assert_eq!(mapped.len(), tm2.len());
+        for (k, v) in tm2.iter() {
+            assert_eq!(mapped.get(k), Some(&v.to_string()));
+        }
+    }
+
+    #[test]
+    fn scope_guard() {
+        let mut tm1 = TieredMap::new();
+        tm1.insert("a", 1);
+
+        {
+            let mut guard = tm1.scope_guard(true);
+            guard.insert("b", 2);
+            guard.scope(|child| {
+                assert_eq!(child.get("a"), Some(&1));
+                child.insert("a", 2);
+                assert_eq!(child.len(), 2);
+            });
+            assert_eq!(guard.get("a"), Some(&2));
+        }
+        assert_eq!(tm1.get("a"), Some(&2));
+        assert_eq!(tm1.get("b"), Some(&2));
+
+        {
+            let mut guard = tm1.scope_guard(false);
+            guard.insert("c", 3);
+            assert!(guard.contains_key("c") && guard.contains_key("a"));
+        }
+        assert!(!tm1.contains_key("c"));
+
+        {
+            let mut guard = tm1.scope_guard(false);
+            guard.insert("c", 3);
+            guard.set_merge(true);
+        }
+        assert_eq!(tm1.get("c"), Some(&3));
+        assert_eq!(tm1.len(), 3);
+    }
+
+    #[test]
+    fn scope_guard_remove() {
+        let mut tm1 = TieredMap::new();
+        tm1.insert("a", 1);
+        tm1.insert("b", 1);
+
+        let mut tm2 = tm1.new_scope();
+        tm2.insert("c", 2);
+
+        {
+            let mut guard = tm2.scope_guard(false);
+            guard.scope(|child| child.remove("a"));
+            assert_eq!(guard.get("a"), None);
+            guard.scope(|child| assert!(!child.contains_key("a")));
+        }
+        assert_eq!(tm2.get("a"), Some(&1));
+
+        {
+            let mut guard = tm2.scope_guard(true);
+            guard.scope(|child| {
+                child.remove("a");
+                child.remove("c");
+            });
+            assert!(!guard.contains_key("a") && !guard.contains_key("c"));
+        }
+        assert_eq!(tm2.get("a"), None);
+        assert_eq!(tm2.get("c"), None);
+        assert_eq!(tm2.len(), 1);
+        assert_eq!(tm1.get("a"), Some(&1));
+
+        {
+            let mut guard = tm2.scope_guard(true);
+            guard.scope(|child| child.remove("b"));
+            guard.insert("b", 3);
+        }
+        assert_eq!(tm2.get("b"), Some(&3));
+        assert_eq!(tm2.len(), 1);
+    }
+
+    #[test]
+    fn locate() {
+        let mut tm1 = TieredMap::new();
+        tm1.insert("a", 1);
+        tm1.insert("b", 1);
+        tm1.insert("d", 1);
+
+        let mut tm2 = tm1.new_scope();
+        tm2.insert("b", 2);
+
+        let mut tm3 = tm2.new_scope();
+        tm3.insert("c", 3);
+        tm3.remove("d");
+
+        assert_eq!(tm3.locate("c"), Location::Local);
+        assert_eq!(tm3.locate("b"), Location::Inherited(1));
+        assert_eq!(tm3.locate("a"), Location::Inherited(2));
+        assert_eq!(tm3.locate("d"), Location::Absent);
+        assert_eq!(tm3.locate("x"), Location::Absent);
+        assert_eq!(tm1.locate("d"), Location::Local);
+    }
+
+    #[test]
+    fn iter_shadowed() {
+        let mut tm1 = TieredMap::new();
+        tm1.insert("a", 1);
+        tm1.insert("b", 1);
+        tm1.insert("c", 1);
+
+        let mut tm2 = tm1.new_scope();
+        tm2.insert("a", 2);
+
+        let mut tm3 = tm2.new_scope();
+        tm3.insert("a", 3);
+        tm3.remove("c");
+
+        let mut shadowed = tm3.iter_shadowed().collect::<Vec<_>>();
+        shadowed.sort();
+        assert_eq!(shadowed, vec![(&"a", &1, 2), (&"a", &2, 1), (&"c", &1, 2)]);
+        assert_eq!(tm1.iter_shadowed().count(), 0);
+    }
+
+    #[test]
+    fn flatten_outlives_chain() {
+        fn detach() -> TieredMap<'static, &'static str, i32> {
+            let mut tm1 = TieredMap::new();
+            tm1.insert("a", 1);
+            tm1.insert("b", 1);
+
+            let mut tm2 = tm1.new_scope();
+            tm2.insert("b", 2);
+            tm2.flatten()
+        }
+
+        let tm = detach();
+        assert!(tm.parent().is_none());
+        assert_eq!(tm.len(), 2);
+        assert_eq!(tm.get("a"), Some(&1));
+        assert_eq!(tm.get("b"), Some(&2));
+    }
+
+    #[test]
+    fn saturating_len() {
+        let mut tm = TieredMap::new();
+        tm.insert("a", 1);
+        tm.parent_size = usize::MAX;
+        tm.parent_cap = usize::MAX;
+
+        assert_eq!(tm.len(), usize::MAX);
+        assert_eq!(tm.capacity(), usize::MAX);
+    }
+
+    #[test]
+    fn filtered() {
+        let mut tm1 = TieredMap::new();
+        tm1.insert("a", 1);
+        tm1.insert("b", 2);
+        tm1.insert("c", 3);
+
+        let mut tm2 = tm1.new_scope();
+        tm2.insert("b", 5);
+        tm2.insert("d", 4);
+
+        let tm = tm2.filtered(|_, &v| v % 2 == 1);
+        assert!(tm.parent().is_none());
+        assert_eq!(tm.len(), 3);
+        assert_eq!(tm.get("a"), Some(&1));
+        assert_eq!(tm.get("b"), Some(&5));
+        assert_eq!(tm.get("c"), Some(&3));
+        assert_eq!(tm2.len(), 4);
+    }
+
+    #[test]
+    fn and_modify_cow() {
+        let mut tm1 = TieredMap::new();
+        tm1.insert("a", 1);
+
+        let mut tm2 = tm1.new_scope();
+        assert_eq!(*tm2.entry("a").and_modify_cow(|v| *v += 10).or_insert(0), 11);
+        assert_eq!(*tm2.entry("b").and_modify_cow(|v| *v += 10).or_insert(0), 0);
+        assert_eq!(tm2.get_local("a"), Some(&11));
+        assert_eq!(tm2.len(), 2);
+        assert_eq!(tm1.get("a"), Some(&1));
+    }
+
+    #[test]
+    fn root_iter_matches_tiered() {
+        let tm1 = TieredMap::from_pairs((0..100).map(|i| (i, i * 2)));
+        // an empty child walks the deduplicating path
+        let tm2 = tm1.new_scope();
+        assert!(tm2.parent().is_some());
+
+        let mut root = tm1.iter().collect::<Vec<_>>();
+        let mut tiered = tm2.iter().collect::<Vec<_>>();
+        root.sort();
+        tiered.sort();
+        assert_eq!(root, tiered);
+        assert_eq!(tm1.iter().fold(0, |sum, (_, v)| sum + v),
+                   tm2.iter().fold(0, |sum, (_, v)| sum + v));
+        assert_eq!(tm1.values().sum::<i32>(), tm2.values().sum::<i32>());
+        assert_eq!(tm1.keys().max(), tm2.keys().max());
+
+        let mut iter = tm1.iter();
+        assert_eq!(iter.len(), 100);
+        iter.next();
+        assert_eq!(iter.len(), 99);
+    }
+
+    #[test]
+    fn contains_key_at_depth() {
+        let mut tm1 = TieredMap::new();
+        tm1.insert("a", 1);
+
+        let mut tm2 = tm1.new_scope();
+        tm2.insert("b", 2);
+
+        let mut tm3 = tm2.new_scope();
         tm3.insert("a", 3);
 
-        assert_eq!(tm2.get("a"), Some(&1));
-        assert_eq!(tm3.get("a"), Some(&3));
+        assert!(tm3.contains_key_at_depth("a", 0));
+        assert!(!tm3.contains_key_at_depth("a", 1));
+        assert!(tm3.contains_key_at_depth("a", 2));
+        assert!(!tm3.contains_key_at_depth("a", 3));
+        assert!(tm3.contains_key_at_depth("b", 1));
+    }
+
+    #[test]
+    fn extend_from() {
+        let mut src1 = TieredMap::new();
+        src1.insert("a", 1);
+        src1.insert("b", 1);
+
+        let mut src2 = src1.new_scope();
+        src2.insert("b", 2);
+        src2.insert("c", 2);
+
+        let mut tm = TieredMap::new();
+        tm.insert("d", 0);
+        tm.extend_from(&src2);
+
+        assert_eq!(tm.len(), 4);
+        assert_eq!(tm.tier_len(), 4);
+        for (k, v) in src2.iter() {
+            assert_eq!(tm.get(k), Some(v));
+        }
+    }
+
+    #[test]
+    fn capacity_three_tiers() {
+        let mut tm1 = TieredMap::<u32, u32>::with_capacity(10);
+        tm1.insert(0, 0);
+
+        let mut tm2 = tm1.new_scope_with_capacity(20);
+        tm2.insert(1, 1);
+
+        let mut tm3 = tm2.new_scope_with_capacity(30);
+        tm3.insert(2, 2);
+
+        let locals = tm1.capacity_local() + tm2.capacity_local() + tm3.capacity_local();
+        assert_eq!(tm1.capacity(), tm1.capacity_local());
+        assert_eq!(tm2.capacity(), tm1.capacity_local() + tm2.capacity_local());
+        assert_eq!(tm3.capacity(), locals);
+        assert!(tm3.capacity_local() >= 30);
+        assert!(tm3.capacity() >= 60);
+    }
+
+    #[test]
+    fn iter_by_tier() {
+        let mut tm1 = TieredMap::new();
+        for i in 0..10 {
+            tm1.insert(i, 1);
+        }
+
+        let mut tm2 = tm1.new_scope();
+        for i in 5..15 {
+            tm2.insert(i, 2);
+        }
+
+        let entries = tm2.iter_by_tier().collect::<Vec<_>>();
+        assert_eq!(entries.len(), 20);
+        assert!(entries[..10].iter().all(|&(d, _, &v)| d == 1 && v == 1));
+        assert!(entries[10..].iter().all(|&(d, _, &v)| d == 0 && v == 2));
+    }
+
+    #[test]
+    fn get_checked() {
+        let mut tm1 = TieredMap::new();
+        tm1.insert("a".to_string(), 1);
+        let tm2 = tm1.new_scope();
+
+        assert_eq!(tm2.get_checked("a"), Ok(&1));
+        let err = tm2.get_checked("b").unwrap_err();
+        assert_eq!(err, KeyNotFound { key: "b".to_string() });
+        assert_eq!(err.to_string(), r#"key not found: "b""#);
+    }
+
+    #[test]
+    fn id() {
+        let mut tm1 = TieredMap::new();
+        tm1.insert("a", 1);
+
+        let tm2 = tm1.clone();
+        assert_eq!(tm1, tm2);
+        assert_ne!(tm1.id(), tm2.id());
+
+        let tm3 = tm1.new_scope();
+        assert_ne!(tm3.id(), tm1.id());
+        assert_eq!(tm3.parent().unwrap().id(), tm1.id());
+    }
+
+    #[test]
+    fn keys_at_depth() {
+        let mut tm1 = TieredMap::new();
+        tm1.insert("a", 1);
+
+        let mut tm2 = tm1.new_scope();
+        tm2.insert("a", 2);
+        tm2.insert("b", 2);
+
+        let mut tm3 = tm2.new_scope();
+        tm3.insert("c", 3);
+
+        let mut keys = tm3.keys_at_depth(1).unwrap().cloned().collect::<Vec<_>>();
+        keys.sort();
+        assert_eq!(keys, vec!["a", "b"]);
+        assert_eq!(tm3.values_at_depth(1).unwrap().sum::<i32>(), 4);
+        assert_eq!(tm3.values_at_depth(2).unwrap().collect::<Vec<_>>(), vec![&1]);
+        assert!(tm3.keys_at_depth(3).is_none());
+        assert!(tm3.values_at_depth(3).is_none());
     }
 
     #[test]
-    fn iter() {
-        let mut tm = TieredMap::new();
-        let mut hm = HashMap::new();
+    fn merge_all_last_wins() {
+        let a = TieredMap::from_pairs(vec![("x", 1), ("y", 1)]);
+        let mut b1 = TieredMap::new();
+        b1.insert("y", 2);
+        let mut b = b1.new_scope();
+        b.insert("z", 2);
+        let c = TieredMap::from_pairs(vec![("z", 3), ("w", 3)]);
 
-        let entries = &[("a", 0u8), ("d", 3), ("c", 2), ("b", 1)];
-        let entries2 = &[("x", 23u8), ("y", 24), ("z", 25)];
+        let merged = super::merge_all(&[&a, &b, &c]);
+        assert!(merged.parent().is_none());
+        assert_eq!(merged.len(), 4);
+        assert_eq!(merged.get("x"), Some(&1));
+        assert_eq!(merged.get("y"), Some(&2));
+        assert_eq!(merged.get("z"), Some(&3));
+        assert_eq!(merged.get("w"), Some(&3));
 
-        for &(k, v) in entries {
-            tm.insert(k, v);
-            hm.insert(k, v);
+        assert!(super::merge_all::<&str, i32, RandomState>(&[]).is_empty());
+    }
+
+    #[test]
+    fn memory_estimate() {
+        let mut tm1 = TieredMap::<u64, u64>::new();
+        assert_eq!(tm1.memory_estimate(), 0);
+
+        let mut last = 0;
+        for i in 0..100 {
+            tm1.insert(i, i);
+            assert!(tm1.memory_estimate() >= last);
+            last = tm1.memory_estimate();
         }
+        assert!(last >= 100 * 16);
 
-        let mut tm2 = tm.new_scope();
+        let mut tm2 = tm1.new_scope();
+        assert_eq!(tm2.memory_estimate(), last);
+        tm2.insert(0, 1);
+        assert!(tm2.memory_estimate() > last);
+    }
 
-        for &(k, v) in entries2 {
-            tm2.insert(k, v);
-            hm.insert(k, v);
+    #[test]
+    fn split_off_local() {
+        let mut tm1 = TieredMap::new();
+        tm1.insert("a", 1);
+        tm1.insert("b", 1);
+
+        let mut tm2 = tm1.new_scope();
+        tm2.insert("b", 2);
+        tm2.insert("c", 2);
+
+        let local = tm2.split_off_local();
+        assert!(local.parent().is_none());
+        assert_eq!(local.len(), 2);
+        assert_eq!(local.get("b"), Some(&2));
+        assert_eq!(local.get("c"), Some(&2));
+
+        assert!(tm2.tier_is_empty());
+        assert_eq!(tm2.len(), 2);
+        assert_eq!(tm2.get("b"), Some(&1));
+        assert_eq!(tm2.get("c"), None);
+    }
+
+    #[test]
+    fn eq_hashmap() {
+        let mut tm1 = TieredMap::new();
+        tm1.insert("a", 1);
+        tm1.insert("b", 1);
+
+        let mut tm2 = tm1.new_scope();
+        tm2.insert("b", 2);
+
+        let mut expected = HashMap::new();
+        expected.insert("a", 1);
+        expected.insert("b", 2);
+        assert_eq!(tm2, expected);
+        assert_eq!(expected, tm2);
+        assert!(tm1 != expected);
+
+        expected.insert("c", 3);
+        assert!(tm2 != expected);
+        assert!(expected != tm2);
+    }
+
+    #[test]
+    fn or_default() {
+        let mut tm1 = TieredMap::new();
+        tm1.insert("a", 1);
+
+        let mut tm2 = tm1.new_scope();
+        tm2.insert("b", 2);
+
+        assert_eq!(tm2.get_or_default("a"), 1);
+        assert_eq!(tm2.get_or_default("b"), 2);
+        assert_eq!(tm2.get_or_default("c"), 0);
+        assert!(!tm2.contains_key("c"));
+
+        *tm2.entry_or_default("a") += 10;
+        *tm2.entry_or_default("b") += 10;
+        *tm2.entry_or_default("c") += 10;
+        assert_eq!(tm2.get_local("a"), Some(&11));
+        assert_eq!(tm2.get_local("b"), Some(&12));
+        assert_eq!(tm2.get_local("c"), Some(&10));
+        assert_eq!(tm1.get("a"), Some(&1));
+        assert_eq!(tm2.len(), 3);
+    }
+
+    #[test]
+    fn to_vec() {
+        let mut tm1 = TieredMap::new();
+        tm1.insert("a", 1);
+        tm1.insert("b", 1);
+
+        let mut tm2 = tm1.new_scope();
+        tm2.insert("b", 2);
+        tm2.insert("c", 2);
+
+        let mut entries = tm2.to_vec();
+        assert_eq!(entries.len(), tm2.effective_len());
+        entries.sort();
+        entries.dedup_by_key(|&mut (k, _)| k);
+        assert_eq!(entries, vec![(&"a", &1), (&"b", &2), (&"c", &2)]);
+    }
+
+    #[test]
+    fn contains_all() {
+        let mut tm1 = TieredMap::new();
+        tm1.insert("a", 1);
+
+        let mut tm2 = tm1.new_scope();
+        tm2.insert("b", 2);
+
+        assert!(tm2.contains_all(&["a", "b"]));
+        assert!(!tm2.contains_all(&["a", "b", "c"]));
+        assert!(tm2.contains_all::<&str>(&[]));
+
+        assert!(tm2.contains_any(&["c", "a"]));
+        assert!(tm2.contains_any(&["b"]));
+        assert!(!tm2.contains_any(&["c", "d"]));
+        assert!(!tm2.contains_any::<&str>(&[]));
+    }
+
+    #[test]
+    fn max_depth() {
+        let mut tm1 = TieredMap::new().with_max_depth(3);
+        tm1.insert("a", 1);
+
+        let mut tm2 = tm1.try_new_scope().unwrap();
+        tm2.insert("b", 2);
+
+        let mut tm3 = tm2.new_scope();
+        tm3.insert("c", 3);
+        assert_eq!(tm3.depth(), 3);
+        assert_eq!(tm3.try_new_scope().err(), Some(DepthLimitExceeded));
+
+        // an empty tier is skipped, so this links to tm2 and fits
+        let mut tm4 = tm3.clone();
+        tm4.clear();
+        assert!(tm4.try_new_scope().is_ok());
+
+        assert!(TieredMap::<u8, u8>::new().try_new_scope().is_ok());
+    }
+
+    #[test]
+    fn max_depth_with_parent() {
+        let mut tm1 = TieredMap::new().with_max_depth(2);
+        tm1.insert("a", 1);
+        let mut tm2 = tm1.new_scope();
+        tm2.insert("b", 2);
+
+        // rebasing isn't checked, but nothing more fits below tm2
+        let tm3 = TieredMap::new().with_parent(&tm2);
+        assert_eq!(tm3.depth(), 3);
+        assert_eq!(tm3.try_new_scope().err(), Some(DepthLimitExceeded));
+
+        // the tier's own cap still applies under an uncapped parent
+        let mut root = TieredMap::new();
+        root.insert("a", 1);
+        let mut tm4 = TieredMap::new().with_max_depth(3).with_parent(&root);
+        tm4.insert("b", 2);
+        let mut tm5 = tm4.try_new_scope().unwrap();
+        tm5.insert("c", 3);
+        assert_eq!(tm5.depth(), 3);
+        assert_eq!(tm5.try_new_scope().err(), Some(DepthLimitExceeded));
+
+        // and the tighter of the two caps wins
+        let mut tm6 = TieredMap::new().with_max_depth(10).with_parent(&tm1);
+        tm6.insert("b", 2);
+        assert_eq!(tm6.try_new_scope().err(), Some(DepthLimitExceeded));
+    }
+
+    #[test]
+    #[should_panic(expected = "scope depth limit exceeded")]
+    fn max_depth_with_hasher() {
+        let mut tm1 = TieredMap::new().with_max_depth(2);
+        tm1.insert("a", 1);
+
+        // the empty tm2 is skipped, so the cap is only hit one tier later
+        let tm2 = tm1.new_scope_with_hasher(RandomState::new());
+        let mut tm3 = tm2.new_scope_with_hasher(RandomState::new());
+        assert_eq!(tm3.depth(), 2);
+        tm3.insert("b", 2);
+        let _ = tm3.new_scope_with_hasher(RandomState::new());
+    }
+
+    #[test]
+    #[should_panic(expected = "scope depth limit exceeded")]
+    fn max_depth_panics() {
+        let mut tm1 = TieredMap::new().with_max_depth(1);
+        tm1.insert("a", 1);
+        let _ = tm1.new_scope();
+    }
+
+    #[test]
+    fn entry_inherited() {
+        let mut tm1 = TieredMap::new();
+        tm1.insert("a", 1);
+        tm1.insert("b", 1);
+
+        let mut tm2 = tm1.new_scope();
+        tm2.insert("b", 2);
+        tm2.insert("c", 2);
+        tm2.remove("a");
+
+        assert!(tm2.entry("b").inherited());
+        assert!(!tm2.entry("c").inherited());
+        assert!(!tm2.entry("d").inherited());
+        // a removed key no longer shadows anything visible
+        assert!(!tm2.entry("a").inherited());
+
+        let mut tm3 = tm2.new_scope();
+        assert!(tm3.entry("c").inherited());
+        assert!(tm3.entry("b").inherited());
+    }
+
+    #[test]
+    fn deep_clone() {
+        let mut tm1 = TieredMap::new();
+        tm1.insert("a", 1);
+        tm1.insert("b", 1);
+
+        let deep = {
+            let mut tm2 = tm1.new_scope();
+            tm2.insert("b", 2);
+            tm2.remove("a");
+
+            let mut tm3 = tm2.new_scope();
+            tm3.insert("c", 3);
+            tm3.deep_clone()
+        };
+
+        tm1.insert("a", 10);
+        tm1.insert("d", 10);
+
+        assert_eq!(deep.len(), 2);
+        assert_eq!(deep.get("a"), None);
+        assert_eq!(deep.get("b"), Some(&2));
+        assert_eq!(deep.get("c"), Some(&3));
+        assert_eq!(deep.get("d"), None);
+
+        let root = deep.parent().and_then(|p| p.parent()).unwrap();
+        assert!(root.parent().is_none());
+        assert_eq!(root.get("a"), Some(&1));
+    }
+
+    #[test]
+    fn get_many() {
+        let mut tm1 = TieredMap::new();
+        tm1.insert("a".to_string(), 1);
+
+        let mut tm2 = tm1.new_scope();
+        tm2.insert("b".to_string(), 2);
+
+        assert_eq!(tm2.get_many(&["b", "x", "a", "b"]),
+                   vec![Some(&2), None, Some(&1), Some(&2)]);
+        assert!(tm2.get_many::<str>(&[]).is_empty());
+    }
+
+    #[test]
+    fn prune_empty() {
+        let mut tm1 = TieredMap::new();
+        tm1.insert("a", 1);
+        tm1.insert("b", 1);
+
+        let tm2 = tm1.new_scope();
+        let tm3 = tm2.new_scope_with_capacity(100);
+        let mut local = HashMap::new();
+        local.insert("b", 4);
+        local.insert("c", 4);
+
+        let tm4 = TieredMap::from(local).with_parent(&tm3);
+        assert_eq!(tm4.depth(), 3);
+        let cap = tm4.capacity_local();
+
+        let tm4 = tm4.prune_empty();
+        assert_eq!(tm4.depth(), 2);
+        assert_eq!(tm4.capacity(), tm1.capacity() + cap);
+        assert_eq!(tm4.len(), 3);
+        assert_eq!(tm4.get("a"), Some(&1));
+        assert_eq!(tm4.get("b"), Some(&4));
+        assert_eq!(tm4.get("c"), Some(&4));
+        assert_eq!(tm1.prune_empty().depth(), 1);
+    }
+
+    #[test]
+    fn insert_ref() {
+        let mut tm1 = TieredMap::new();
+        tm1.insert("a", vec![1]);
+
+        let mut tm2 = tm1.new_scope();
+        tm2.insert_ref("a", vec![2]).push(3);
+        tm2.insert_ref("b", vec![]).push(4);
+        tm2.insert_ref("b", vec![5]).push(6);
+
+        assert_eq!(tm2.get("a"), Some(&vec![2, 3]));
+        assert_eq!(tm2.get("b"), Some(&vec![5, 6]));
+        assert_eq!(tm2.len(), 2);
+        assert_eq!(tm1.get("a"), Some(&vec![1]));
+    }
+
+    #[test]
+    fn with_capacity_for_chain() {
+        let mut tm1 = TieredMap::with_capacity_for_chain(64);
+        assert!(tm1.capacity_local() >= 64);
+        let cap = tm1.capacity_local();
+        for i in 0..64 {
+            tm1.insert(i, i);
         }
+        assert_eq!(tm1.capacity_local(), cap);
 
-        assert_eq!(hm.iter().collect::<HashSet<_>>(),
-                   tm2.iter().collect::<HashSet<_>>());
+        let tm2 = tm1.new_scope();
+        assert_eq!(tm2.capacity_local(), 0);
+        assert_eq!(tm2.capacity(), cap);
+    }
 
-        let mut iter1 = hm.iter();
-        let mut iter2 = tm2.iter();
+    #[test]
+    fn rollback() {
+        let mut tm1 = TieredMap::new();
+        tm1.insert("a", 1);
+        tm1.insert("b", 1);
+        tm1.insert("c", 1);
 
-        let (mut a, mut b);
+        let mut tm2 = tm1.new_scope();
+        tm2.insert("x", 2);
+        tm2.remove("c");
+        let cp = tm2.checkpoint();
 
-        loop {
-            assert_eq!(iter1.size_hint(), iter2.size_hint());
+        tm2.insert("y", 2);
+        tm2.insert("a", 2);
+        tm2.insert("c", 2);
+        tm2.remove("b");
+        tm2.insert("x", 3);
+        assert_eq!(tm2.len(), 4);
 
-            a = iter1.next();
-            b = iter2.next();
+        tm2.rollback(cp);
+        let mut local = tm2.iter_local().collect::<Vec<_>>();
+        local.sort();
+        assert_eq!(local, vec![(&"x", &3)]);
+        assert_eq!(tm2.get("a"), Some(&1));
+        assert_eq!(tm2.get("b"), Some(&1));
+        assert_eq!(tm2.get("c"), None);
+        assert_eq!(tm2.len(), 3);
+        assert_eq!(tm2.len(), tm2.effective_len());
+    }
 
-            if a.is_none() || b.is_none() {
-                break;
-            }
+    #[test]
+    #[should_panic(expected = "checkpoint taken from tier")]
+    fn rollback_other_tier() {
+        let mut a = TieredMap::new();
+        a.insert("a", 1);
+        let b = TieredMap::<&str, i32>::new();
+
+        a.rollback(b.checkpoint());
+    }
+
+    #[test]
+    fn append() {
+        let mut tm1 = TieredMap::new();
+        tm1.insert("a", 1);
+
+        let mut tm2 = tm1.new_scope();
+        tm2.insert("b", 2);
+
+        let mut other = TieredMap::from_pairs(vec![("b", 3), ("c", 3)]);
+        tm2.append(&mut other);
+        assert!(other.is_empty());
+        assert_eq!(tm2.len(), 3);
+        assert_eq!(tm2.get("b"), Some(&3));
+        assert_eq!(tm2.get("c"), Some(&3));
+
+        // only the local tier of a child is moved
+        let mut other2 = tm1.new_scope();
+        other2.insert("d", 4);
+        let mut tm3 = TieredMap::new();
+        tm3.append(&mut other2);
+        assert_eq!(tm3.len(), 1);
+        assert_eq!(other2.len(), 1);
+        assert!(other2.tier_is_empty());
+    }
+
+    #[test]
+    fn iter_sorted() {
+        let mut tm1 = TieredMap::new();
+        tm1.insert("c", 1);
+        tm1.insert("a", 1);
+        tm1.insert("b", 1);
+
+        let mut tm2 = tm1.new_scope();
+        tm2.insert("b", 2);
+        tm2.insert("d", 2);
+
+        let flat = TieredMap::from_pairs(vec![("d", 2), ("b", 2), ("a", 1), ("c", 1)]);
+        assert_eq!(tm2, flat);
+        assert_eq!(tm2.iter_sorted(), flat.iter_sorted());
+        assert_eq!(flat.iter_sorted(), vec![(&"a", &1), (&"b", &2), (&"c", &1), (&"d", &2)]);
+    }
+
+    #[test]
+    fn tier_histogram() {
+        let mut tm1 = TieredMap::new();
+        for i in 0..5 {
+            tm1.insert(i, 1);
         }
+
+        let tm2 = tm1.new_scope_with_capacity(1);
+        // linked past the empty tm2
+        let mut tm3 = tm2.new_scope();
+        tm3.insert(0, 3);
+        tm3.insert(10, 3);
+
+        let mut tm4 = tm3.new_scope();
+        tm4.insert(20, 4);
+
+        assert_eq!(tm4.tier_histogram(), vec![(0, 1), (1, 2), (2, 5)]);
+        assert_eq!(tm1.tier_histogram(), vec![(0, 5)]);
     }
 
     #[test]
-    fn from_iter() {
-        let entries = vec![("a", 0u8), ("d", 3), ("c", 2), ("b", 1), ("z", 4)];
-        let len = entries.len();
+    fn get_cow() {
+        let mut tm1 = TieredMap::new();
+        tm1.insert("a", "one".to_string());
 
-        // TODO: fix inference
-        let tm = TieredMap::<_, _, RandomState>::from_iter(entries);
+        let mut tm2 = tm1.new_scope();
+        tm2.insert("b", "two".to_string());
 
-        assert_eq!(len, tm.len());
+        match tm2.get_cow("a") {
+            Some(Cow::Borrowed(v)) => assert_eq!(v, "one"),
+            _ => panic!("expected a borrowed inherited value"),
+        }
+        match tm2.get_cow("b") {
+            Some(Cow::Borrowed(v)) => assert_eq!(v, "two"),
+            _ => panic!("expected a borrowed local value"),
+        }
+        assert!(tm2.get_cow("c").is_none());
+        assert_eq!(tm2.get_cow("a").unwrap().into_owned(), "one");
+    }
+
+    #[test]
+    fn for_each_tier() {
+        let mut tm1 = TieredMap::new();
+        tm1.insert("a", 1);
+        tm1.insert("b", 1);
+
+        let mut tm2 = tm1.new_scope();
+        tm2.insert("b", 2);
+
+        let mut tm3 = tm2.new_scope();
+        tm3.insert("c", 3);
+
+        let mut total = 0;
+        let mut depths = Vec::new();
+        tm3.for_each_tier(|depth, map| {
+            total += map.len();
+            depths.push(depth);
+        });
+        assert_eq!(total, tm1.tier_len() + tm2.tier_len() + tm3.tier_len());
+        assert_eq!(depths, vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn tier_of() {
+        let mut tm1 = TieredMap::new();
+        tm1.insert("a", 1);
+        tm1.insert("b", 1);
+        tm1.insert("c", 1);
+
+        let mut tm2 = tm1.new_scope();
+        tm2.insert("b", 2);
+
+        let mut tm3 = tm2.new_scope();
+        tm3.insert("d", 3);
+        tm3.remove("c");
+
+        let owner = tm3.tier_of("a").unwrap();
+        assert_eq!(owner.id(), tm1.id());
+        assert_eq!(owner.tier_len(), 3);
+        assert_eq!(tm3.tier_of("b").unwrap().id(), tm2.id());
+        assert_eq!(tm3.tier_of("d").unwrap().id(), tm3.id());
+        assert!(tm3.tier_of("c").is_none());
+        assert!(tm3.tier_of("x").is_none());
+    }
+
+    #[test]
+    fn prune_empty_depth_limit() {
+        let mut tm1 = TieredMap::new().with_max_depth(4);
+        tm1.insert("a", 1);
+
+        let tm2 = tm1.new_scope();
+        let tm3 = TieredMap::new().with_parent(&tm2);
+        let mut tm4 = TieredMap::new().with_parent(&tm3);
+        tm4.insert("b", 2);
+        assert_eq!(tm4.depth(), 4);
+        assert!(tm4.try_new_scope().is_err());
+
+        let mut tm4 = tm4.prune_empty();
+        assert_eq!(tm4.depth(), 2);
+        tm4.insert("c", 3);
+
+        let mut tm5 = tm4.try_new_scope().unwrap();
+        tm5.insert("d", 4);
+        let mut tm6 = tm5.try_new_scope().unwrap();
+        tm6.insert("e", 5);
+        assert_eq!(tm6.depth(), 4);
+        assert_eq!(tm6.try_new_scope().err(), Some(DepthLimitExceeded));
+        assert_eq!(tm6.len(), 5);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_round_trip() {
+        extern crate serde_json;
+
+        let mut tm1 = TieredMap::new();
+        tm1.insert("a".to_string(), 1);
+        tm1.insert("b".to_string(), 1);
+
+        let mut tm2 = tm1.new_scope();
+        tm2.insert("a".to_string(), 2);
+        tm2.insert("c".to_string(), 2);
+
+        let json = serde_json::to_string(&tm2).unwrap();
+        let flat: TieredMap<String, i32> = serde_json::from_str(&json).unwrap();
+        assert!(flat.parent().is_none());
+        assert_eq!(flat.get("a"), Some(&2));
+        assert_eq!(flat.len(), 3);
+        assert_eq!(flat, tm2);
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn par_iter() {
+        use rayon::prelude::*;
+
+        let mut tm1 = TieredMap::new();
+        for i in 0..1000u64 {
+            tm1.insert(i, i);
+        }
+
+        let mut tm2 = tm1.new_scope();
+        for i in 500..1500u64 {
+            tm2.insert(i, i * 2);
+        }
+        tm2.remove(&0);
+
+        assert_eq!(tm2.par_iter().count(), tm2.len());
+        assert_eq!(tm2.par_iter().map(|(_, v)| v).sum::<u64>(),
+                   tm2.iter().map(|(_, v)| v).sum::<u64>());
     }
 }
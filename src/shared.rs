@@ -0,0 +1,346 @@
+
+use core::borrow::Borrow;
+use core::hash::{Hash, BuildHasher};
+use alloc::rc::Rc;
+
+use collections::{HashMap, HashSet, RandomState};
+use collections::hash_map;
+
+/// A tiered map whose parents are shared through `Rc` instead of borrowed.
+///
+/// Children own a handle to their parent, so scopes can be stored, returned
+/// and share a parent with their siblings.
+pub struct SharedTieredMap<K, V, H = RandomState> {
+    parent: Option<Rc<SharedTieredMap<K, V, H>>>,
+    map: HashMap<K, V, H>,
+    parent_size: usize,
+    // number of inherited keys hidden by this tier
+    shadowed: usize,
+    // inherited keys removed from this tier
+    removed: HashSet<K>,
+}
+
+impl<K, V> SharedTieredMap<K, V, RandomState>
+    where K: Eq + Hash
+{
+    pub fn new() -> Self {
+        Self::with_hasher(RandomState::default())
+    }
+}
+
+impl<K, V, H> SharedTieredMap<K, V, H>
+    where K: Eq + Hash,
+          H: BuildHasher
+{
+    pub fn with_hasher(hash_builder: H) -> Self {
+        SharedTieredMap {
+            parent: None,
+            map: HashMap::with_hasher(hash_builder),
+            parent_size: 0,
+            shadowed: 0,
+            removed: HashSet::new(),
+        }
+    }
+
+    // links an already built tier, keeping its bookkeeping as is
+    pub(crate) fn from_parts(parent: Option<Rc<Self>>,
+                             map: HashMap<K, V, H>,
+                             removed: HashSet<K>,
+                             parent_size: usize,
+                             shadowed: usize)
+                             -> Self {
+        SharedTieredMap { parent, map, parent_size, shadowed, removed }
+    }
+
+    /// Creates a child scope holding a new handle to `parent`.
+    ///
+    /// Unlike `TieredMap::new_scope`, empty tiers are always linked: a
+    /// skipped tier would have no handle held on it, so it could still be
+    /// mutated through `Rc::get_mut` behind the child's back.
+    pub fn new_scope(parent: &Rc<Self>) -> Self
+        where H: Clone
+    {
+        SharedTieredMap {
+            parent: Some(parent.clone()),
+            map: HashMap::with_hasher(parent.map.hasher().clone()),
+            parent_size: parent.len(),
+            shadowed: 0,
+            removed: HashSet::new(),
+        }
+    }
+
+    pub fn parent(&self) -> Option<&Rc<Self>> {
+        self.parent.as_ref()
+    }
+
+    pub fn len(&self) -> usize {
+        self.parent_size - self.shadowed + self.map.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn get<Q>(&self, k: &Q) -> Option<&V>
+        where K: Borrow<Q>,
+              Q: ?Sized + Hash + Eq
+    {
+        self.get_key_value(k).map(|(_, v)| v)
+    }
+
+    pub fn contains_key<Q>(&self, k: &Q) -> bool
+        where K: Borrow<Q>,
+              Q: ?Sized + Hash + Eq
+    {
+        self.get(k).is_some()
+    }
+
+    pub fn insert(&mut self, k: K, v: V) -> Option<V> {
+        match self.map.entry(k) {
+            hash_map::Entry::Occupied(mut e) => Some(e.insert(v)),
+            hash_map::Entry::Vacant(e) => {
+                // a tombstone already counts as hiding the inherited key
+                if !self.removed.remove(e.key()) &&
+                   self.parent.as_ref().is_some_and(|p| p.contains_key(e.key())) {
+                    self.shadowed += 1;
+                }
+                e.insert(v);
+                None
+            }
+        }
+    }
+
+    /// Removes `k` from the current tier, returning its local value.
+    ///
+    /// A key that is only inherited is hidden from this tier onwards instead,
+    /// and `None` is returned.
+    pub fn remove<Q>(&mut self, k: &Q) -> Option<V>
+        where K: Borrow<Q> + Clone,
+              Q: ?Sized + Hash + Eq
+    {
+        if let Some(v) = self.map.remove(k) {
+            if self.parent.as_ref().is_some_and(|p| p.contains_key(k)) {
+                self.shadowed -= 1;
+            }
+            return Some(v);
+        }
+
+        if !self.removed.contains(k) {
+            let inherited = self.parent.as_ref().and_then(|p| p.get_key_value(k));
+            if let Some(pk) = inherited.map(|(pk, _)| pk.clone()) {
+                self.removed.insert(pk);
+                self.shadowed += 1;
+            }
+        }
+
+        None
+    }
+
+    fn get_key_value<Q>(&self, k: &Q) -> Option<(&K, &V)>
+        where K: Borrow<Q>,
+              Q: ?Sized + Hash + Eq
+    {
+        let mut tier = self;
+        loop {
+            if let Some(kv) = tier.map.get_key_value(k) {
+                return Some(kv);
+            }
+            if tier.removed.contains(k) {
+                return None;
+            }
+            match tier.parent {
+                Some(ref p) => tier = p,
+                None => return None,
+            }
+        }
+    }
+
+    pub fn iter(&self) -> Iter<'_, K, V, H> {
+        Iter {
+            tier: self,
+            iter: self.map.iter(),
+            seen: HashSet::new(),
+            remaining: self.len(),
+        }
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = &K> {
+        self.iter().map(|(k, _)| k)
+    }
+
+    pub fn values(&self) -> impl Iterator<Item = &V> {
+        self.iter().map(|(_, v)| v)
+    }
+}
+
+impl<K, V, H> Clone for SharedTieredMap<K, V, H>
+    where K: Clone,
+          V: Clone,
+          H: Clone
+{
+    /// Clones the current tier, sharing the parent with the original.
+    fn clone(&self) -> Self {
+        SharedTieredMap {
+            parent: self.parent.clone(),
+            map: self.map.clone(),
+            parent_size: self.parent_size,
+            shadowed: self.shadowed,
+            removed: self.removed.clone(),
+        }
+    }
+}
+
+impl<K, V, H> Default for SharedTieredMap<K, V, H>
+    where K: Eq + Hash,
+          H: BuildHasher + Default
+{
+    fn default() -> Self {
+        Self::with_hasher(H::default())
+    }
+}
+
+pub struct Iter<'a, K: 'a, V: 'a, H: 'a> {
+    tier: &'a SharedTieredMap<K, V, H>,
+    iter: hash_map::Iter<'a, K, V>,
+    // keys yielded from nearer tiers, which hide any farther entries
+    seen: HashSet<&'a K>,
+    remaining: usize,
+}
+
+impl<'a, K, V, H> Iterator for Iter<'a, K, V, H>
+    where K: Eq + Hash
+{
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            match self.iter.next() {
+                Some((k, v)) => {
+                    if self.seen.contains(k) {
+                        continue; // shadowed
+                    }
+                    if self.tier.parent.is_some() {
+                        self.seen.insert(k);
+                    }
+                    self.remaining -= 1;
+                    return Some((k, v));
+                }
+                None => {
+                    match self.tier.parent {
+                        None => return None,
+                        Some(ref p) => {
+                            self.seen.extend(self.tier.removed.iter());
+                            self.tier = p;
+                            self.iter = p.map.iter();
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl<'a, K, V, H> ExactSizeIterator for Iter<'a, K, V, H>
+    where K: Eq + Hash
+{
+    #[inline]
+    fn len(&self) -> usize {
+        self.remaining
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+
+    use super::SharedTieredMap;
+
+    #[test]
+    fn remove() {
+        let mut root = SharedTieredMap::new();
+        root.insert("a", 1);
+        root.insert("b", 1);
+        let root = Rc::new(root);
+
+        let mut scope = SharedTieredMap::new_scope(&root);
+        scope.insert("b", 2);
+        assert_eq!(scope.remove("a"), None);
+        assert_eq!(scope.remove("b"), Some(2));
+
+        assert_eq!(scope.get("a"), None);
+        assert_eq!(scope.get("b"), Some(&1));
+        assert_eq!(scope.len(), 1);
+        assert_eq!(scope.iter().collect::<Vec<_>>(), vec![(&"b", &1)]);
+
+        scope.insert("a", 3);
+        assert_eq!(scope.get("a"), Some(&3));
+        assert_eq!(scope.len(), 2);
+        assert_eq!(root.len(), 2);
+    }
+
+    #[test]
+    fn sibling_scopes() {
+        let mut root = SharedTieredMap::new();
+        root.insert("a", 1);
+        root.insert("b", 1);
+        let root = Rc::new(root);
+
+        let mut scopes = Vec::new();
+        for i in 0..3 {
+            let mut scope = SharedTieredMap::new_scope(&root);
+            scope.insert("b", i + 10);
+            scope.insert("c", i + 10);
+            scopes.push(scope);
+        }
+        drop(root);
+
+        for (i, scope) in scopes.iter().enumerate() {
+            assert_eq!(scope.get("a"), Some(&1));
+            assert_eq!(scope.get("b"), Some(&(i + 10)));
+            assert_eq!(scope.len(), 3);
+            assert_eq!(scope.iter().len(), 3);
+        }
+        assert_eq!(Rc::strong_count(scopes[0].parent().unwrap()), 3);
+    }
+
+    #[test]
+    fn nested() {
+        let mut tm1 = SharedTieredMap::new();
+        tm1.insert(1, "a");
+        let tm2 = Rc::new(SharedTieredMap::new_scope(&Rc::new(tm1)));
+
+        // empty tiers are still linked, so tm2 can't change under tm3
+        let mut tm3 = SharedTieredMap::new_scope(&tm2);
+        assert!(Rc::ptr_eq(tm3.parent().unwrap(), &tm2));
+        tm3.insert(1, "b");
+        tm3.insert(2, "b");
+
+        let mut values = tm3.values().cloned().collect::<Vec<_>>();
+        values.sort();
+        assert_eq!(values, vec!["b", "b"]);
+        assert_eq!(tm3.keys().count(), 2);
+        assert_eq!(tm2.get(&1), Some(&"a"));
+    }
+
+    #[test]
+    fn empty_parent_stays_shared() {
+        let mut root = SharedTieredMap::new();
+        root.insert("a", 1);
+        let root = Rc::new(root);
+
+        let mut scope = Rc::new(SharedTieredMap::new_scope(&root));
+        let child = SharedTieredMap::new_scope(&scope);
+        assert!(Rc::get_mut(&mut scope).is_none());
+
+        drop(child);
+        Rc::get_mut(&mut scope).unwrap().insert("x", 2);
+        let child = SharedTieredMap::new_scope(&scope);
+        assert_eq!(child.get("x"), Some(&2));
+        assert_eq!(child.len(), 2);
+    }
+}